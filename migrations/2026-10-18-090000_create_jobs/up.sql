@@ -0,0 +1,11 @@
+-- Your SQL goes here
+create table jobs (
+    id integer primary key not null,
+    server_id integer not null references servers(id),
+    status text not null,
+    created_at timestamp not null default current_timestamp,
+    started_at timestamp,
+    finished_at timestamp,
+    exit_code integer,
+    error_message text
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table jobs drop column error;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+alter table jobs add column error text;
\ No newline at end of file
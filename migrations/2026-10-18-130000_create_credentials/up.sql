@@ -0,0 +1,5 @@
+-- Your SQL goes here
+create table credentials (
+    login text primary key not null,
+    password text not null
+);
\ No newline at end of file
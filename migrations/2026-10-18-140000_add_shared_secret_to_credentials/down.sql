@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table credentials drop column shared_secret;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+alter table credentials add column shared_secret text;
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+alter table servers drop column branch_password;
+alter table servers drop column branch;
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+alter table servers add column branch text;
+alter table servers add column branch_password text;
\ No newline at end of file
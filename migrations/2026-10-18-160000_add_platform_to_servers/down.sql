@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+alter table servers drop column installed_platform;
+alter table servers drop column platform;
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+alter table servers add column platform text;
+alter table servers add column installed_platform text;
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+alter table jobs drop column files_intact;
+alter table jobs drop column kind;
\ No newline at end of file
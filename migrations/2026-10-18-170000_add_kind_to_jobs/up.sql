@@ -0,0 +1,3 @@
+-- Your SQL goes here
+alter table jobs add column kind text not null default 'update';
+alter table jobs add column files_intact boolean;
\ No newline at end of file
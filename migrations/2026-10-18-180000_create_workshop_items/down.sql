@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+alter table servers drop column mods_link;
+alter table servers drop column mods_dir;
+alter table servers drop column workshop_app_id;
+drop table workshop_items;
\ No newline at end of file
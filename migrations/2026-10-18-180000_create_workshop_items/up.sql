@@ -0,0 +1,12 @@
+-- Your SQL goes here
+create table workshop_items (
+    server_id integer not null references servers(id),
+    item_id bigint not null,
+    status text not null default 'pending',
+    error_message text,
+    updated_at timestamp,
+    primary key (server_id, item_id)
+);
+alter table servers add column workshop_app_id integer;
+alter table servers add column mods_dir text;
+alter table servers add column mods_link text not null default 'symlink';
\ No newline at end of file
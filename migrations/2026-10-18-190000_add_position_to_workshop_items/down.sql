@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table workshop_items drop column position;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+alter table workshop_items add column position integer not null default 0;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+drop table steamcmd_installs;
\ No newline at end of file
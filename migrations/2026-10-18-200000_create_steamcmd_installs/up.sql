@@ -0,0 +1,7 @@
+-- Your SQL goes here
+create table steamcmd_installs (
+    id integer primary key not null,
+    version text,
+    sha256 text not null,
+    installed_at timestamp not null default current_timestamp
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+drop table job_attempts;
\ No newline at end of file
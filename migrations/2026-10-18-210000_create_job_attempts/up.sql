@@ -0,0 +1,11 @@
+-- Your SQL goes here
+create table job_attempts (
+    id integer primary key not null,
+    job_id integer not null references jobs(id),
+    attempt integer not null,
+    started_at timestamp not null,
+    finished_at timestamp not null,
+    exit_code integer,
+    error text,
+    error_message text
+);
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+alter table servers drop column update_checked_at;
+alter table servers drop column update_available;
+alter table servers drop column latest_build_id;
+alter table servers drop column installed_build_id;
\ No newline at end of file
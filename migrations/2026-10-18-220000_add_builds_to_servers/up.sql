@@ -0,0 +1,5 @@
+-- Your SQL goes here
+alter table servers add column installed_build_id bigint;
+alter table servers add column latest_build_id bigint;
+alter table servers add column update_available boolean not null default 0;
+alter table servers add column update_checked_at timestamp;
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+alter table servers drop column next_update_at;
+alter table servers drop column maintenance_window;
+alter table servers drop column update_schedule;
+alter table servers drop column update_policy;
\ No newline at end of file
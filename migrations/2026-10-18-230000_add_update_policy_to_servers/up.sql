@@ -0,0 +1,5 @@
+-- Your SQL goes here
+alter table servers add column update_policy text not null default 'never';
+alter table servers add column update_schedule text;
+alter table servers add column maintenance_window text;
+alter table servers add column next_update_at timestamp;
\ No newline at end of file
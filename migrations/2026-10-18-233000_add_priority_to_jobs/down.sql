@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table jobs drop column priority;
\ No newline at end of file
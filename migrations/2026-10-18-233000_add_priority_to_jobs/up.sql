@@ -0,0 +1,2 @@
+-- Your SQL goes here
+alter table jobs add column priority integer not null default 0;
\ No newline at end of file
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+create table depot_pins (
+    server_id integer not null references servers(id),
+    depot_id integer not null,
+    manifest_id text not null,
+    pinned_at timestamp not null,
+    primary key (server_id, depot_id)
+);
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+create table snapshots (
+    id integer primary key not null,
+    server_id integer not null references servers(id),
+    job_id integer not null references jobs(id),
+    mode text not null,
+    path text not null,
+    build_id bigint,
+    created_at timestamp not null
+);
\ No newline at end of file
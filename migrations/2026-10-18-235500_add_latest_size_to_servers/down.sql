@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table servers drop column latest_size;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+alter table servers add column latest_size bigint;
\ No newline at end of file
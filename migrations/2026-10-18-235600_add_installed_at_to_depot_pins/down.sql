@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+alter table depot_pins drop column installed_at;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+alter table depot_pins add column installed_at timestamp;
\ No newline at end of file
@@ -0,0 +1,248 @@
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::AsChangeset;
+
+use crate::{
+    app_manifest::InstalledBuild,
+    install::Platform,
+    keyvalues::{KeyValues, Value},
+    schema::servers,
+};
+
+/// An app's info as steamcmd prints it with `app_info_print`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppInfo(KeyValues);
+
+impl AppInfo {
+    /// Picks the app's KeyValues block out of everything else steamcmd
+    /// prints, e.g.
+    ///
+    /// ```text
+    /// AppID : 740, change number : 20194432/0, last change : Wed Oct 25 2023
+    /// "740"
+    /// {
+    ///     ...
+    /// }
+    /// ```
+    pub fn from_output(output: &str, app_id: i32) -> Result<Self> {
+        let start = format!("\"{}\"", app_id);
+        let mut block = String::new();
+        let mut depth = 0;
+        let mut closed = false;
+        // steamcmd puts every brace on a line of its own.
+        for line in output.lines().skip_while(|l| l.trim() != start) {
+            block.push_str(line);
+            block.push('\n');
+            match line.trim() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => continue,
+            }
+            if depth == 0 {
+                closed = true;
+                break;
+            }
+        }
+        if !closed {
+            anyhow::bail!("steamcmd printed no app info for {}", app_id);
+        }
+        let document = KeyValues::parse(&block)?;
+        match document.section(&app_id.to_string()) {
+            Some(info) => Ok(AppInfo(info.clone())),
+            None => anyhow::bail!("steamcmd printed no app info for {}", app_id),
+        }
+    }
+
+    /// The build currently released on a branch, where `None` is the default
+    /// public branch.
+    pub fn build_id(&self, branch: Option<&str>) -> Option<u64> {
+        self.0
+            .section("depots")?
+            .section("branches")?
+            .section(branch.unwrap_or("public"))?
+            .string("buildid")?
+            .parse()
+            .ok()
+    }
+
+    /// The installed size of the app's own depots for a platform, as of the
+    /// branch's current manifests or the public branch's when the branch does
+    /// not list its own.
+    pub fn size(&self, branch: Option<&str>, platform: Platform) -> Option<u64> {
+        let mut total = None;
+        for (depot_id, depot) in self.0.section("depots")?.entries() {
+            let depot = match (depot_id.parse::<u32>(), depot) {
+                (Ok(_), Value::Section(depot)) => depot,
+                _ => continue,
+            };
+            // Redistributables shared with other apps.
+            if depot.string("depotfromapp").is_some() {
+                continue;
+            }
+            let oslist = depot
+                .section("config")
+                .and_then(|config| config.string("oslist"))
+                .filter(|oslist| !oslist.is_empty());
+            if let Some(oslist) = oslist {
+                if !oslist.split(',').any(|os| os.trim() == platform.as_str()) {
+                    continue;
+                }
+            }
+            let manifests = depot.section("manifests");
+            let size = manifests
+                .and_then(|m| {
+                    m.section(branch.unwrap_or("public"))
+                        .or(m.section("public"))
+                })
+                .and_then(|manifest| manifest.string("size"))
+                .or_else(|| depot.string("maxsize"))
+                .and_then(|size| size.parse::<u64>().ok());
+            if let Some(size) = size {
+                total = Some(total.unwrap_or(0) + size);
+            }
+        }
+        total
+    }
+}
+
+/// What the manager last found out about a server's builds.
+#[derive(Debug, Clone, PartialEq, Eq, AsChangeset)]
+#[table_name = "servers"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct BuildStatus {
+    pub installed_build_id: Option<i64>,
+    pub latest_build_id: Option<i64>,
+    pub update_available: bool,
+    pub update_checked_at: Option<NaiveDateTime>,
+    pub latest_size: Option<i64>,
+}
+
+impl BuildStatus {
+    /// An update is only reported when both builds are known, so a failed
+    /// lookup never asks for one.
+    pub fn new(
+        installed: Option<&InstalledBuild>,
+        latest: Option<u64>,
+        latest_size: Option<u64>,
+        checked_at: Option<NaiveDateTime>,
+    ) -> Self {
+        let installed = installed.map(|build| build.build_id as i64);
+        let latest = latest.map(|build_id| build_id as i64);
+        BuildStatus {
+            installed_build_id: installed,
+            latest_build_id: latest,
+            update_available: matches!((installed, latest), (Some(i), Some(l)) if i != l),
+            update_checked_at: checked_at,
+            latest_size: latest_size.map(|size| size as i64),
+        }
+    }
+
+    /// A pinned server's files come from its pinned manifests rather than
+    /// any build, so it has no build installed and never an update.
+    pub fn pinned(self) -> Self {
+        BuildStatus {
+            installed_build_id: None,
+            update_available: false,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OUTPUT: &str = r#"Steam Console Client (c) Valve Corporation - version 1698262904
+Connecting anonymously to Steam Public...OK
+AppID : 740, change number : 20194432/0, last change : Wed Oct 25 19:41:44 2023
+"740"
+{
+	"common"
+	{
+		"name"		"Counter-Strike Global Offensive - Dedicated Server"
+		"type"		"Tool"
+	}
+	"depots"
+	{
+		"228990"
+		{
+			"depotfromapp"		"228980"
+		}
+		"741"
+		{
+			"name"		"Counter-Strike Global Offensive - Dedicated Server Content"
+			"manifests"
+			{
+				"public"
+				{
+					"gid"		"3543226452394537624"
+					"size"		"34034618270"
+				}
+			}
+		}
+		"742"
+		{
+			"config"
+			{
+				"oslist"		"windows"
+			}
+			"maxsize"		"2000"
+		}
+		"branches"
+		{
+			"public"
+			{
+				"buildid"		"12489351"
+				"timeupdated"		"1698262904"
+			}
+			"1.38.0.0"
+			{
+				"buildid"		"8013453"
+				"description"		"Legacy build"
+			}
+		}
+	}
+}
+Unloading Steam API...OK
+"#;
+
+    #[test]
+    fn test_build_id() -> anyhow::Result<()> {
+        let info = AppInfo::from_output(OUTPUT, 740)?;
+
+        assert_eq!(Some(12489351), info.build_id(None));
+        assert_eq!(Some(8013453), info.build_id(Some("1.38.0.0")));
+        assert_eq!(None, info.build_id(Some("missing")));
+        assert!(AppInfo::from_output(OUTPUT, 730).is_err());
+        assert!(AppInfo::from_output("\"740\"\n{\n", 740).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pinned_build_status() {
+        let status = BuildStatus::new(None, Some(12489351), Some(30), None);
+        assert_eq!(Some(12489351), status.latest_build_id);
+
+        let status = BuildStatus {
+            installed_build_id: Some(8013453),
+            update_available: true,
+            ..status
+        }
+        .pinned();
+        assert_eq!(None, status.installed_build_id);
+        assert!(!status.update_available);
+        assert_eq!(Some(12489351), status.latest_build_id);
+    }
+
+    #[test]
+    fn test_size() -> anyhow::Result<()> {
+        let info = AppInfo::from_output(OUTPUT, 740)?;
+
+        assert_eq!(Some(34034618270), info.size(None, Platform::Linux));
+        assert_eq!(
+            Some(34034620270),
+            info.size(Some("1.38.0.0"), Platform::Windows)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::keyvalues::{KeyValues, Value};
+
+/// steamcmd's `StateFlags` bits, named as in `EAppState`.
+const STATE_FLAGS: [(u32, &str); 23] = [
+    (1, "uninstalled"),
+    (1 << 1, "update_required"),
+    (1 << 2, "fully_installed"),
+    (1 << 3, "encrypted"),
+    (1 << 4, "locked"),
+    (1 << 5, "files_missing"),
+    (1 << 6, "app_running"),
+    (1 << 7, "files_corrupt"),
+    (1 << 8, "update_running"),
+    (1 << 9, "update_paused"),
+    (1 << 10, "update_started"),
+    (1 << 11, "uninstalling"),
+    (1 << 12, "backup_running"),
+    (1 << 16, "reconfiguring"),
+    (1 << 17, "validating"),
+    (1 << 18, "adding_files"),
+    (1 << 19, "preallocating"),
+    (1 << 20, "downloading"),
+    (1 << 21, "staging"),
+    (1 << 22, "committing"),
+    (1 << 23, "update_stopping"),
+    (1 << 24, "update_pausing"),
+    (1 << 25, "update_resuming"),
+];
+
+/// A depot as it is installed, from the manifest's `InstalledDepots`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstalledDepot {
+    pub depot_id: u32,
+    pub manifest: String,
+    pub size: u64,
+}
+
+/// What steamcmd last installed for an app, read from
+/// `steamapps/appmanifest_<appid>.acf` in its install directory.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstalledBuild {
+    pub app_id: i32,
+    pub name: Option<String>,
+    pub build_id: u64,
+    pub size_on_disk: u64,
+    pub last_updated: Option<NaiveDateTime>,
+    /// The beta branch the installed files came from, if not the default.
+    pub branch: Option<String>,
+    pub state_flags: u32,
+    /// `state_flags` by name.
+    pub state: Vec<&'static str>,
+    pub depots: Vec<InstalledDepot>,
+}
+
+impl InstalledBuild {
+    pub fn path(install_dir: &Path, app_id: i32) -> PathBuf {
+        install_dir
+            .join("steamapps")
+            .join(format!("appmanifest_{}.acf", app_id))
+    }
+
+    /// Reads the app's manifest, which is missing until steamcmd has installed
+    /// it at least once.
+    pub async fn read(install_dir: &Path, app_id: i32) -> Result<Option<Self>> {
+        let path = Self::path(install_dir, app_id);
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Self::parse(&contents)
+            .with_context(|| format!("could not read {}", path.display()))
+            .map(Some)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let document = KeyValues::parse(contents)?;
+        let state = document
+            .section("AppState")
+            .ok_or_else(|| anyhow::anyhow!("manifest has no AppState"))?;
+        let number = |key: &str| -> Result<u64> {
+            match state.string(key) {
+                Some(value) => value
+                    .parse()
+                    .with_context(|| format!("{} is not a number: {}", key, value)),
+                None => Ok(0),
+            }
+        };
+        let app_id = state
+            .string("appid")
+            .ok_or_else(|| anyhow::anyhow!("manifest has no appid"))?
+            .parse()?;
+        let state_flags = number("StateFlags")? as u32;
+        let last_updated = match number("LastUpdated")? {
+            0 => None,
+            secs => NaiveDateTime::from_timestamp_opt(secs as i64, 0),
+        };
+        // MountedConfig is what is on disk, UserConfig what was asked for;
+        // older manifests only have the latter.
+        let branch = ["MountedConfig", "UserConfig"]
+            .iter()
+            .find_map(|config| state.section(config)?.string("BetaKey"))
+            .filter(|branch| !branch.is_empty() && *branch != "public")
+            .map(String::from);
+        let mut depots = vec![];
+        if let Some(installed) = state.section("InstalledDepots") {
+            for (depot_id, depot) in installed.entries() {
+                let depot = match depot {
+                    Value::Section(depot) => depot,
+                    Value::String(_) => continue,
+                };
+                depots.push(InstalledDepot {
+                    depot_id: depot_id.parse()?,
+                    manifest: depot.string("manifest").unwrap_or_default().into(),
+                    size: depot.string("size").unwrap_or("0").parse()?,
+                });
+            }
+        }
+
+        Ok(InstalledBuild {
+            app_id,
+            name: state.string("name").map(String::from),
+            build_id: number("buildid")?,
+            size_on_disk: number("SizeOnDisk")?,
+            last_updated,
+            branch,
+            state_flags,
+            state: state_names(state_flags),
+            depots,
+        })
+    }
+}
+
+pub fn state_names(flags: u32) -> Vec<&'static str> {
+    if flags == 0 {
+        return vec!["invalid"];
+    }
+    STATE_FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MANIFEST: &str = r#""AppState"
+{
+	"appid"		"740"
+	"Universe"		"1"
+	"name"		"Counter-Strike Global Offensive - Dedicated Server"
+	"StateFlags"		"6"
+	"installdir"		"Counter-Strike Global Offensive Beta - Dedicated Server"
+	"LastUpdated"		"1698262904"
+	"SizeOnDisk"		"34145267814"
+	"buildid"		"12489351"
+	"LastOwner"		"0"
+	"InstalledDepots"
+	{
+		"741"
+		{
+			"manifest"		"3543226452394537624"
+			"size"		"34034618270"
+		}
+	}
+	"UserConfig"
+	{
+		"BetaKey"		"1.38.0.0"
+	}
+	"MountedConfig"
+	{
+		"BetaKey"		"public"
+	}
+}
+"#;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let build = InstalledBuild::parse(MANIFEST)?;
+
+        assert_eq!(740, build.app_id);
+        assert_eq!(12489351, build.build_id);
+        assert_eq!(34145267814, build.size_on_disk);
+        assert_eq!(
+            "2023-10-25 19:41:44",
+            build.last_updated.unwrap().to_string()
+        );
+        assert_eq!(None, build.branch);
+        assert_eq!(vec!["update_required", "fully_installed"], build.state);
+        assert_eq!(
+            vec![InstalledDepot {
+                depot_id: 741,
+                manifest: "3543226452394537624".into(),
+                size: 34034618270,
+            }],
+            build.depots
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_branch_falls_back_to_user_config() -> anyhow::Result<()> {
+        let manifest = MANIFEST.replace("\"MountedConfig\"", "\"Other\"");
+        let build = InstalledBuild::parse(&manifest)?;
+        assert_eq!(Some(String::from("1.38.0.0")), build.branch);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert_eq!(None, InstalledBuild::read(dir.path(), 740).await?);
+
+        std::fs::create_dir(dir.path().join("steamapps"))?;
+        std::fs::write(InstalledBuild::path(dir.path(), 740), MANIFEST)?;
+        let build = InstalledBuild::read(dir.path(), 740).await?.unwrap();
+        assert_eq!(12489351, build.build_id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_names() {
+        assert_eq!(vec!["invalid"], state_names(0));
+        assert_eq!(
+            vec!["fully_installed", "update_running", "update_started"],
+            state_names(0x504)
+        );
+    }
+}
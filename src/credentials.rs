@@ -0,0 +1,46 @@
+use crate::schema::credentials;
+use diesel::{Insertable, Queryable};
+use serde::{Deserialize, Serialize};
+
+/// The login name steamcmd accepts without a password.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// A Steam account that servers can log in with. The password and the mobile
+/// authenticator's shared secret can be set through the API but are never
+/// sent back.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Queryable, Insertable)]
+#[table_name = "credentials"]
+pub struct Credential {
+    pub login: String,
+    #[serde(skip_serializing)]
+    pub password: String,
+    #[serde(default, skip_serializing)]
+    pub shared_secret: Option<String>,
+}
+
+impl Credential {
+    pub fn new(login: &str, password: &str) -> Self {
+        Credential {
+            login: login.into(),
+            password: password.into(),
+            shared_secret: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_password_not_serialized() -> anyhow::Result<()> {
+        let credential: Credential =
+            serde_json::from_str(r#"{"login": "operator", "password": "hunter2"}"#)?;
+        assert_eq!(Credential::new("operator", "hunter2"), credential);
+        assert_eq!(
+            r#"{"login":"operator"}"#,
+            serde_json::to_string(&credential)?
+        );
+        Ok(())
+    }
+}
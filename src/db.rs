@@ -1,6 +1,15 @@
-use crate::install::Server;
+use crate::app_info::BuildStatus;
+use crate::credentials::Credential;
+use crate::depots::DepotPin;
+use crate::install::{Platform, Server};
+use crate::jobs::{Job, JobAttempt, JobKind, JobOutcome, JobStatus, NewJob, NewJobAttempt};
+use crate::schedule::UpdateSchedule;
+use crate::snapshot::{NewSnapshot, Snapshot};
+use crate::steamcmd::{NewSteamCmdInstall, SteamCmdInstall};
+use crate::workshop::{ItemStatus, WorkshopItem};
 
-use diesel::{delete, insert_into, prelude::*};
+use chrono::NaiveDateTime;
+use diesel::{delete, insert_into, insert_or_ignore_into, prelude::*, replace_into, update};
 use rocket_sync_db_pools::database;
 // use diesel::sqlite::SqliteConnection;
 //use rusqlite::{params, Connection};
@@ -9,6 +18,45 @@ use rocket_sync_db_pools::database;
 //     conn: SqliteConnection,
 // }
 
+/// Stores an enum in a `Text` column by way of its `as_str` and `FromStr`
+/// implementations.
+macro_rules! text_column {
+    ($type:ty) => {
+        impl diesel::serialize::ToSql<diesel::sql_types::Text, diesel::sqlite::Sqlite> for $type {
+            fn to_sql<W: std::io::Write>(
+                &self,
+                out: &mut diesel::serialize::Output<W, diesel::sqlite::Sqlite>,
+            ) -> diesel::serialize::Result {
+                <str as diesel::serialize::ToSql<diesel::sql_types::Text, diesel::sqlite::Sqlite>>::to_sql(
+                    self.as_str(),
+                    out,
+                )
+            }
+        }
+
+        impl diesel::deserialize::FromSql<diesel::sql_types::Text, diesel::sqlite::Sqlite>
+            for $type
+        {
+            fn from_sql(
+                bytes: Option<&<diesel::sqlite::Sqlite as diesel::backend::Backend>::RawValue>,
+            ) -> diesel::deserialize::Result<Self> {
+                let value = <String as diesel::deserialize::FromSql<
+                    diesel::sql_types::Text,
+                    diesel::sqlite::Sqlite,
+                >>::from_sql(bytes)?;
+                Ok(value.parse()?)
+            }
+        }
+    };
+}
+
+no_arg_sql_function!(
+    last_insert_rowid,
+    diesel::sql_types::Integer,
+    "Returns the rowid of the most recent successful INSERT"
+);
+
+#[derive(Clone)]
 pub struct DBStorage {}
 
 #[database("sqlite_db")]
@@ -22,31 +70,600 @@ pub struct Db(diesel::SqliteConnection);
 // }
 
 impl DBStorage {
-    pub async fn save(&self, server: &Server, db: Db) -> anyhow::Result<()> {
+    pub async fn save(&self, server: &Server, db: &Db) -> anyhow::Result<()> {
         use crate::schema::servers::dsl::*;
         let save_server = server.clone();
         db.run(move |conn| insert_into(servers).values(save_server).execute(conn))
             .await?;
         Ok(())
     }
-    pub async fn load(&self, server_id: i32, db: Db) -> anyhow::Result<Server> {
+    pub async fn load(&self, server_id: i32, db: &Db) -> anyhow::Result<Server> {
         use crate::schema::servers::dsl::*;
         let server = db
             .run(move |conn| servers.find(server_id).first::<Server>(conn))
             .await?;
         Ok(server)
     }
-    pub async fn list(&self, db: Db) -> anyhow::Result<Vec<Server>> {
+    pub async fn list(&self, db: &Db) -> anyhow::Result<Vec<Server>> {
         use crate::schema::servers::dsl::*;
         let results = db.run(move |conn| servers.load::<Server>(conn)).await?;
         Ok(results)
     }
 
-    pub async fn delete(&self, server_id: i32, db: Db) -> anyhow::Result<()> {
+    pub async fn set_installed_platform(
+        &self,
+        server_id: i32,
+        target: Platform,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::servers::dsl::*;
+        db.run(move |conn| {
+            update(servers.find(server_id))
+                .set(installed_platform.eq(Some(target)))
+                .execute(conn)
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_build_status(
+        &self,
+        server_id: i32,
+        status: BuildStatus,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::servers::dsl::*;
+        db.run(move |conn| update(servers.find(server_id)).set(status).execute(conn))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_update_schedule(
+        &self,
+        server_id: i32,
+        schedule: UpdateSchedule,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::servers::dsl::*;
+        db.run(move |conn| update(servers.find(server_id)).set(schedule).execute(conn))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_next_update(
+        &self,
+        server_id: i32,
+        next: Option<NaiveDateTime>,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::servers::dsl::*;
+        db.run(move |conn| {
+            update(servers.find(server_id))
+                .set(next_update_at.eq(next))
+                .execute(conn)
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, server_id: i32, db: &Db) -> anyhow::Result<()> {
         use crate::schema::servers::dsl::*;
         db.run(move |conn| delete(servers.filter(id.eq(server_id))).execute(conn))
             .await?;
 
         Ok(())
     }
+
+    pub async fn save_job(&self, job: &NewJob, db: &Db) -> anyhow::Result<Job> {
+        use crate::schema::jobs::dsl::*;
+        let new_job = job.clone();
+        let job = db
+            .run(move |conn| {
+                conn.transaction(|| {
+                    insert_into(jobs).values(new_job).execute(conn)?;
+                    let job_id = diesel::select(last_insert_rowid).get_result::<i32>(conn)?;
+                    jobs.find(job_id).first::<Job>(conn)
+                })
+            })
+            .await?;
+        Ok(job)
+    }
+
+    pub async fn load_job(&self, job_id: i32, db: &Db) -> anyhow::Result<Job> {
+        use crate::schema::jobs::dsl::*;
+        let job = db
+            .run(move |conn| jobs.find(job_id).first::<Job>(conn))
+            .await?;
+        Ok(job)
+    }
+
+    pub async fn find_job(&self, job_id: i32, db: &Db) -> anyhow::Result<Option<Job>> {
+        use crate::schema::jobs::dsl::*;
+        let job = db
+            .run(move |conn| jobs.find(job_id).first::<Job>(conn).optional())
+            .await?;
+        Ok(job)
+    }
+
+    pub async fn list_jobs(&self, for_server: i32, db: &Db) -> anyhow::Result<Vec<Job>> {
+        use crate::schema::jobs::dsl::*;
+        let results = db
+            .run(move |conn| {
+                jobs.filter(server_id.eq(for_server))
+                    .order(id.desc())
+                    .load::<Job>(conn)
+            })
+            .await?;
+        Ok(results)
+    }
+
+    pub async fn set_job_priority(
+        &self,
+        job_id: i32,
+        new_priority: i32,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::jobs::dsl::*;
+        db.run(move |conn| {
+            update(jobs.find(job_id))
+                .set(priority.eq(new_priority))
+                .execute(conn)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Changes what a job does, returning false if it is no longer queued.
+    pub async fn set_queued_job_kind(
+        &self,
+        job_id: i32,
+        new_kind: JobKind,
+        db: &Db,
+    ) -> anyhow::Result<bool> {
+        use crate::schema::jobs::dsl::*;
+        let updated = db
+            .run(move |conn| {
+                update(jobs.find(job_id).filter(status.eq(JobStatus::Queued)))
+                    .set(kind.eq(new_kind))
+                    .execute(conn)
+            })
+            .await?;
+        Ok(updated > 0)
+    }
+
+    /// Jobs that are queued or in progress, oldest first.
+    pub async fn list_unfinished_jobs(&self, db: &Db) -> anyhow::Result<Vec<Job>> {
+        use crate::schema::jobs::dsl::*;
+        let results = db
+            .run(move |conn| {
+                jobs.filter(status.eq_any(vec![
+                    JobStatus::Queued,
+                    JobStatus::Running,
+                    JobStatus::AwaitingGuardCode,
+                ]))
+                .order(id.asc())
+                .load::<Job>(conn)
+            })
+            .await?;
+        Ok(results)
+    }
+
+    /// Moves a queued job to running, returning false if it is no longer
+    /// queued.
+    pub async fn start_job(&self, job_id: i32, at: NaiveDateTime, db: &Db) -> anyhow::Result<bool> {
+        use crate::schema::jobs::dsl::*;
+        let updated = db
+            .run(move |conn| {
+                update(jobs.find(job_id).filter(status.eq(JobStatus::Queued)))
+                    .set((status.eq(JobStatus::Running), started_at.eq(Some(at))))
+                    .execute(conn)
+            })
+            .await?;
+        Ok(updated > 0)
+    }
+
+    pub async fn finish_job(
+        &self,
+        job_id: i32,
+        outcome: JobOutcome,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::jobs::dsl::*;
+        db.run(move |conn| update(jobs.find(job_id)).set(outcome).execute(conn))
+            .await?;
+        Ok(())
+    }
+
+    /// Updates the status of a job that is still in progress.
+    pub async fn set_job_status(
+        &self,
+        job_id: i32,
+        new_status: JobStatus,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::jobs::dsl::*;
+        db.run(move |conn| {
+            update(
+                jobs.find(job_id)
+                    .filter(status.eq_any(vec![JobStatus::Running, JobStatus::AwaitingGuardCode])),
+            )
+            .set(status.eq(new_status))
+            .execute(conn)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Finishes a job that no worker has started, returning false if it is
+    /// no longer queued.
+    pub async fn finish_queued_job(
+        &self,
+        job_id: i32,
+        outcome: JobOutcome,
+        db: &Db,
+    ) -> anyhow::Result<bool> {
+        use crate::schema::jobs::dsl::*;
+        let updated = db
+            .run(move |conn| {
+                update(jobs.find(job_id).filter(status.eq(JobStatus::Queued)))
+                    .set(outcome)
+                    .execute(conn)
+            })
+            .await?;
+        Ok(updated > 0)
+    }
+
+    pub async fn save_job_attempt(&self, record: &NewJobAttempt, db: &Db) -> anyhow::Result<()> {
+        use crate::schema::job_attempts::dsl::*;
+        let new_attempt = record.clone();
+        db.run(move |conn| insert_into(job_attempts).values(new_attempt).execute(conn))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_job_attempts(
+        &self,
+        for_job: i32,
+        db: &Db,
+    ) -> anyhow::Result<Vec<JobAttempt>> {
+        use crate::schema::job_attempts::dsl::*;
+        let results = db
+            .run(move |conn| {
+                job_attempts
+                    .filter(job_id.eq(for_job))
+                    .order(attempt)
+                    .load::<JobAttempt>(conn)
+            })
+            .await?;
+        Ok(results)
+    }
+
+    pub async fn save_credential(&self, credential: &Credential, db: &Db) -> anyhow::Result<()> {
+        use crate::schema::credentials::dsl::*;
+        let save_credential = credential.clone();
+        db.run(move |conn| {
+            replace_into(credentials)
+                .values(save_credential)
+                .execute(conn)
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn load_credential(
+        &self,
+        for_login: &str,
+        db: &Db,
+    ) -> anyhow::Result<Option<Credential>> {
+        use crate::schema::credentials::dsl::*;
+        let for_login = for_login.to_string();
+        let credential = db
+            .run(move |conn| {
+                credentials
+                    .find(for_login)
+                    .first::<Credential>(conn)
+                    .optional()
+            })
+            .await?;
+        Ok(credential)
+    }
+
+    pub async fn list_credentials(&self, db: &Db) -> anyhow::Result<Vec<Credential>> {
+        use crate::schema::credentials::dsl::*;
+        let results = db
+            .run(move |conn| credentials.order(login).load::<Credential>(conn))
+            .await?;
+        Ok(results)
+    }
+
+    pub async fn delete_credential(&self, for_login: &str, db: &Db) -> anyhow::Result<()> {
+        use crate::schema::credentials::dsl::*;
+        let for_login = for_login.to_string();
+        db.run(move |conn| delete(credentials.filter(login.eq(for_login))).execute(conn))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn save_steamcmd_install(
+        &self,
+        install: &NewSteamCmdInstall,
+        db: &Db,
+    ) -> anyhow::Result<SteamCmdInstall> {
+        use crate::schema::steamcmd_installs::dsl::*;
+        let new_install = install.clone();
+        let install = db
+            .run(move |conn| {
+                conn.transaction(|| {
+                    insert_into(steamcmd_installs)
+                        .values(new_install)
+                        .execute(conn)?;
+                    let install_id = diesel::select(last_insert_rowid).get_result::<i32>(conn)?;
+                    steamcmd_installs
+                        .find(install_id)
+                        .first::<SteamCmdInstall>(conn)
+                })
+            })
+            .await?;
+        Ok(install)
+    }
+
+    pub async fn latest_steamcmd_install(
+        &self,
+        db: &Db,
+    ) -> anyhow::Result<Option<SteamCmdInstall>> {
+        use crate::schema::steamcmd_installs::dsl::*;
+        let install = db
+            .run(move |conn| {
+                steamcmd_installs
+                    .order(id.desc())
+                    .first::<SteamCmdInstall>(conn)
+                    .optional()
+            })
+            .await?;
+        Ok(install)
+    }
+
+    pub async fn save_depot_pin(&self, pin: &DepotPin, db: &Db) -> anyhow::Result<()> {
+        use crate::schema::depot_pins::dsl::*;
+        let save_pin = pin.clone();
+        db.run(move |conn| replace_into(depot_pins).values(save_pin).execute(conn))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_depot_pins(&self, for_server: i32, db: &Db) -> anyhow::Result<Vec<DepotPin>> {
+        use crate::schema::depot_pins::dsl::*;
+        let results = db
+            .run(move |conn| {
+                depot_pins
+                    .filter(server_id.eq(for_server))
+                    .order(depot_id)
+                    .load::<DepotPin>(conn)
+            })
+            .await?;
+        Ok(results)
+    }
+
+    pub async fn set_depot_pins_installed(
+        &self,
+        for_server: i32,
+        at: NaiveDateTime,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::depot_pins::dsl::*;
+        db.run(move |conn| {
+            update(depot_pins.filter(server_id.eq(for_server)))
+                .set(installed_at.eq(at))
+                .execute(conn)
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_depot_pin(
+        &self,
+        for_server: i32,
+        depot: i32,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::depot_pins::dsl::*;
+        db.run(move |conn| delete(depot_pins.find((for_server, depot))).execute(conn))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn save_snapshot(&self, snapshot: &NewSnapshot, db: &Db) -> anyhow::Result<Snapshot> {
+        use crate::schema::snapshots::dsl::*;
+        let new_snapshot = snapshot.clone();
+        let snapshot = db
+            .run(move |conn| {
+                conn.transaction(|| {
+                    insert_into(snapshots).values(new_snapshot).execute(conn)?;
+                    let snapshot_id = diesel::select(last_insert_rowid).get_result::<i32>(conn)?;
+                    snapshots.find(snapshot_id).first::<Snapshot>(conn)
+                })
+            })
+            .await?;
+        Ok(snapshot)
+    }
+
+    /// A server's snapshots, newest first.
+    pub async fn list_snapshots(&self, for_server: i32, db: &Db) -> anyhow::Result<Vec<Snapshot>> {
+        use crate::schema::snapshots::dsl::*;
+        let results = db
+            .run(move |conn| {
+                snapshots
+                    .filter(server_id.eq(for_server))
+                    .order(id.desc())
+                    .load::<Snapshot>(conn)
+            })
+            .await?;
+        Ok(results)
+    }
+
+    pub async fn delete_snapshot(&self, snapshot_id: i32, db: &Db) -> anyhow::Result<()> {
+        use crate::schema::snapshots::dsl::*;
+        db.run(move |conn| delete(snapshots.find(snapshot_id)).execute(conn))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn save_workshop_item(&self, item: &WorkshopItem, db: &Db) -> anyhow::Result<()> {
+        use crate::schema::workshop_items::dsl::*;
+        let save_item = item.clone();
+        db.run(move |conn| replace_into(workshop_items).values(save_item).execute(conn))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_workshop_items(
+        &self,
+        for_server: i32,
+        db: &Db,
+    ) -> anyhow::Result<Vec<WorkshopItem>> {
+        use crate::schema::workshop_items::dsl::*;
+        let results = db
+            .run(move |conn| {
+                workshop_items
+                    .filter(server_id.eq(for_server))
+                    .order((position, item_id))
+                    .load::<WorkshopItem>(conn)
+            })
+            .await?;
+        Ok(results)
+    }
+
+    /// Puts the given items first in the load order, in the order given.
+    pub async fn order_workshop_items(
+        &self,
+        for_server: i32,
+        items: Vec<i64>,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::workshop_items::dsl::*;
+        db.run(move |conn| {
+            conn.transaction(|| {
+                // Anything not listed keeps its place relative to the others,
+                // after the listed items.
+                let rest: Vec<i64> = workshop_items
+                    .filter(server_id.eq(for_server))
+                    .filter(item_id.ne_all(&items))
+                    .order((position, item_id))
+                    .select(item_id)
+                    .load(conn)?;
+                for (at, item) in items.iter().chain(rest.iter()).enumerate() {
+                    update(workshop_items.find((for_server, *item)))
+                        .set(position.eq(at as i32))
+                        .execute(conn)?;
+                }
+                Ok::<_, diesel::result::Error>(())
+            })
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Makes the server's mod list exactly the given items, in order, keeping
+    /// the download status of items it already had.
+    pub async fn replace_workshop_items(
+        &self,
+        for_server: i32,
+        items: Vec<i64>,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::workshop_items::dsl::*;
+        db.run(move |conn| {
+            conn.transaction(|| {
+                delete(
+                    workshop_items
+                        .filter(server_id.eq(for_server))
+                        .filter(item_id.ne_all(&items)),
+                )
+                .execute(conn)?;
+                for (at, item) in items.iter().enumerate() {
+                    insert_or_ignore_into(workshop_items)
+                        .values(WorkshopItem::pending(for_server, *item, at as i32))
+                        .execute(conn)?;
+                    update(workshop_items.find((for_server, *item)))
+                        .set(position.eq(at as i32))
+                        .execute(conn)?;
+                }
+                Ok::<_, diesel::result::Error>(())
+            })
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_workshop_item_status(
+        &self,
+        for_server: i32,
+        item: i64,
+        new_status: ItemStatus,
+        message: Option<String>,
+        at: NaiveDateTime,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::workshop_items::dsl::*;
+        db.run(move |conn| {
+            update(workshop_items.find((for_server, item)))
+                .set((
+                    status.eq(new_status),
+                    error_message.eq(message),
+                    updated_at.eq(Some(at)),
+                ))
+                .execute(conn)
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_workshop_item(
+        &self,
+        for_server: i32,
+        item: i64,
+        db: &Db,
+    ) -> anyhow::Result<()> {
+        use crate::schema::workshop_items::dsl::*;
+        db.run(move |conn| delete(workshop_items.find((for_server, item))).execute(conn))
+            .await?;
+        Ok(())
+    }
+}
+
+/// A database in `dir` with every migration run, for testing the services
+/// against. The pool lives as long as the returned Rocket instance.
+#[cfg(test)]
+pub async fn test_db(
+    dir: &std::path::Path,
+) -> anyhow::Result<(rocket::Rocket<rocket::Ignite>, Db)> {
+    use diesel::connection::SimpleConnection;
+    use rocket::figment::Figment;
+
+    let figment = Figment::from(rocket::Config::debug_default())
+        .merge(("log_level", "off"))
+        .merge((
+            "databases.sqlite_db.url",
+            dir.join("test.db").display().to_string(),
+        ));
+    let rocket = rocket::custom(figment)
+        .attach(Db::fairing())
+        .ignite()
+        .await?;
+    let db = Db::get_one(&rocket)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("could not connect to the test database"))?;
+    let mut migrations = vec![];
+    for entry in std::fs::read_dir("migrations")? {
+        let up = entry?.path().join("up.sql");
+        if up.is_file() {
+            migrations.push(up);
+        }
+    }
+    migrations.sort();
+    for migration in migrations {
+        let sql = std::fs::read_to_string(migration)?;
+        db.run(move |conn| conn.batch_execute(&sql)).await?;
+    }
+    Ok((rocket, db))
 }
@@ -0,0 +1,189 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::app_manifest::InstalledDepot;
+use crate::schema::depot_pins;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::{Insertable, Queryable};
+use serde::{Deserialize, Serialize};
+
+/// Holds one of a server's depots at a fixed manifest. While a server has any
+/// pins it is installed with `download_depot` rather than `app_update`, and
+/// never updated on a schedule.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Queryable, Insertable)]
+pub struct DepotPin {
+    pub server_id: i32,
+    pub depot_id: i32,
+    /// Kept as text, since manifest IDs use the whole of a `u64`.
+    pub manifest_id: String,
+    pub pinned_at: NaiveDateTime,
+    /// When the pinned manifest was installed, which is nothing until the
+    /// next install after pinning it.
+    pub installed_at: Option<NaiveDateTime>,
+}
+
+impl DepotPin {
+    pub fn new(server_id: i32, depot_id: i32, manifest_id: &str) -> Result<Self> {
+        let manifest_id = manifest_id.trim();
+        if manifest_id.parse::<u64>().is_err() {
+            anyhow::bail!("{} is not a depot manifest ID", manifest_id);
+        }
+        Ok(DepotPin {
+            server_id,
+            depot_id,
+            manifest_id: manifest_id.into(),
+            pinned_at: chrono::Utc::now().naive_utc(),
+            installed_at: None,
+        })
+    }
+}
+
+/// The installed size of the pinned depots, which is not known unless every
+/// one of them is installed.
+pub fn pinned_size(pins: &[DepotPin], installed: &[InstalledDepot]) -> Option<u64> {
+    pins.iter()
+        .map(|pin| {
+            installed
+                .iter()
+                .find(|depot| depot.depot_id as i32 == pin.depot_id)
+                .map(|depot| depot.size)
+        })
+        .sum()
+}
+
+/// A depot that `download_depot` finished, which steamcmd leaves in its own
+/// content folder rather than the install directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepotDownload {
+    pub depot_id: i32,
+    pub path: PathBuf,
+}
+
+impl DepotDownload {
+    /// Reads the line steamcmd prints once a depot is downloaded, e.g.
+    /// `Depot download complete : "/home/steam/Steam/steamapps/content/app_740/depot_741" (2376 files, manifest 3543226452394537624)`.
+    pub fn from_line(line: &str) -> Option<Self> {
+        let (_, rest) = line.split_once("Depot download complete : \"")?;
+        let (path, _) = rest.split_once('"')?;
+        let path = PathBuf::from(path);
+        let depot_id = path
+            .file_name()?
+            .to_str()?
+            .strip_prefix("depot_")?
+            .parse()
+            .ok()?;
+        Some(DepotDownload { depot_id, path })
+    }
+
+    /// Moves the depot's files over those in the install directory.
+    pub fn move_into(&self, install_dir: &Path) -> Result<()> {
+        move_dir(&self.path, install_dir)?;
+        fs::remove_dir_all(&self.path)?;
+        Ok(())
+    }
+}
+
+fn move_dir(source: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let to = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            move_dir(&entry.path(), &to)?;
+        } else if fs::rename(entry.path(), &to).is_err() {
+            // steamcmd's folder can be on another filesystem.
+            fs::copy(entry.path(), &to)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pin_needs_manifest_id() {
+        assert!(DepotPin::new(740, 741, "3543226452394537624").is_ok());
+        assert!(DepotPin::new(740, 741, "18446744073709551615").is_ok());
+        assert!(DepotPin::new(740, 741, "latest").is_err());
+    }
+
+    #[test]
+    fn test_pinned_size() -> anyhow::Result<()> {
+        let installed = [
+            InstalledDepot {
+                depot_id: 741,
+                manifest: "3543226452394537624".into(),
+                size: 30,
+            },
+            InstalledDepot {
+                depot_id: 742,
+                manifest: "2970127485730212374".into(),
+                size: 12,
+            },
+        ];
+        let pins = [DepotPin::new(740, 741, "3543226452394537624")?];
+        assert_eq!(Some(30), pinned_size(&pins, &installed));
+        let pins = [
+            DepotPin::new(740, 741, "3543226452394537624")?,
+            DepotPin::new(740, 743, "1111")?,
+        ];
+        assert_eq!(None, pinned_size(&pins, &installed));
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_from_line() {
+        assert_eq!(
+            Some(DepotDownload {
+                depot_id: 741,
+                path: "/home/steam/Steam/steamapps/content/app_740/depot_741".into(),
+            }),
+            DepotDownload::from_line(
+                "Depot download complete : \"/home/steam/Steam/steamapps/content/app_740/depot_741\" (2376 files, manifest 3543226452394537624)"
+            )
+        );
+        assert_eq!(
+            None,
+            DepotDownload::from_line("Downloading depot 741 (34034 MB) ...")
+        );
+    }
+
+    #[test]
+    fn test_move_into() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let depot = dir.path().join("content").join("depot_741");
+        fs::create_dir_all(depot.join("csgo").join("bin"))?;
+        fs::write(depot.join("srcds_run"), "new")?;
+        fs::write(depot.join("csgo").join("bin").join("server.so"), "new")?;
+        let install_dir = dir.path().join("csgo");
+        fs::create_dir_all(install_dir.join("csgo").join("cfg"))?;
+        fs::write(install_dir.join("srcds_run"), "old")?;
+        fs::write(
+            install_dir.join("csgo").join("cfg").join("server.cfg"),
+            "kept",
+        )?;
+
+        DepotDownload {
+            depot_id: 741,
+            path: depot.clone(),
+        }
+        .move_into(&install_dir)?;
+
+        assert_eq!("new", fs::read_to_string(install_dir.join("srcds_run"))?);
+        assert_eq!(
+            "new",
+            fs::read_to_string(install_dir.join("csgo").join("bin").join("server.so"))?
+        );
+        assert_eq!(
+            "kept",
+            fs::read_to_string(install_dir.join("csgo").join("cfg").join("server.cfg"))?
+        );
+        assert!(!depot.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+const GB: f64 = (1u64 << 30) as f64;
+
+/// Space on the filesystem holding a directory.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub path: PathBuf,
+    pub total_bytes: u64,
+    /// What an unprivileged process such as steamcmd can still write.
+    pub free_bytes: u64,
+    pub used_bytes: u64,
+}
+
+impl DiskUsage {
+    /// Looks at the nearest directory that exists, since a server's install
+    /// directory is only made by its first install.
+    pub fn of(path: &Path) -> Result<Self> {
+        let existing = path
+            .ancestors()
+            .find(|p| p.exists())
+            .unwrap_or_else(|| Path::new("."));
+        let c_path = CString::new(existing.as_os_str().as_bytes())?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return Err(anyhow::anyhow!(
+                "could not read free space at {}: {}",
+                existing.display(),
+                std::io::Error::last_os_error()
+            ));
+        }
+        let block = stat.f_frsize as u64;
+        let total_bytes = stat.f_blocks as u64 * block;
+        Ok(DiskUsage {
+            path: path.to_path_buf(),
+            total_bytes,
+            free_bytes: stat.f_bavail as u64 * block,
+            used_bytes: total_bytes.saturating_sub(stat.f_bfree as u64 * block),
+        })
+    }
+}
+
+/// How much more space an install is expected to take, going by the size of
+/// the build it installs and of what is already there. Nothing is known to be
+/// needed when the build's size is not.
+pub fn space_needed(expected_size: Option<u64>, installed_size: Option<u64>) -> u64 {
+    match (expected_size, installed_size) {
+        (Some(expected), Some(installed)) => expected.saturating_sub(installed),
+        (Some(expected), None) => expected,
+        (None, _) => 0,
+    }
+}
+
+pub fn gigabytes(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / GB)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disk_usage() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let usage = DiskUsage::of(&dir.path().join("not").join("made"))?;
+
+        assert!(usage.total_bytes > 0);
+        assert!(usage.free_bytes <= usage.total_bytes);
+        assert!(usage.used_bytes <= usage.total_bytes);
+        assert_eq!(dir.path().join("not").join("made"), usage.path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_space_needed() {
+        assert_eq!(30, space_needed(Some(30), None));
+        assert_eq!(5, space_needed(Some(30), Some(25)));
+        assert_eq!(0, space_needed(Some(20), Some(25)));
+        assert_eq!(0, space_needed(None, Some(25)));
+        assert_eq!("1.5 GB", gigabytes(3 << 29));
+    }
+}
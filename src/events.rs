@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::install::InstallError;
+use crate::progress::Progress;
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+type Channels = Arc<Mutex<HashMap<i32, broadcast::Sender<InstallEvent>>>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InstallEvent {
+    Output {
+        stream: OutputStream,
+        line: String,
+    },
+    Progress(Progress),
+    AwaitingGuardCode,
+    /// The run failed in a way worth trying again, which happens after the
+    /// delay.
+    Retrying {
+        attempt: u32,
+        delay_seconds: u64,
+        error: InstallError,
+    },
+}
+
+/// Fans install output out to every subscriber of a job or a server, so that
+/// each browser tab sees the whole stream rather than a share of it.
+#[derive(Clone, Default)]
+pub struct EventHub {
+    jobs: Channels,
+    servers: Channels,
+}
+
+/// Publishes the output of a single job to both the job and its server.
+/// Channels are looked up on every send rather than held, so that the hub
+/// alone decides when one is dropped.
+pub struct Publisher {
+    hub: EventHub,
+    job_id: i32,
+    server_id: i32,
+}
+
+/// A receiver that drops its channel from the hub once the last subscriber
+/// has gone.
+pub struct Subscription {
+    rx: Option<broadcast::Receiver<InstallEvent>>,
+    channels: Channels,
+    id: i32,
+}
+
+fn subscribe(channels: &Channels, id: i32) -> Subscription {
+    let rx = channels
+        .lock()
+        .unwrap()
+        .entry(id)
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe();
+    Subscription {
+        rx: Some(rx),
+        channels: channels.clone(),
+        id,
+    }
+}
+
+fn send(channels: &Channels, id: i32, msg: InstallEvent) {
+    if let Some(tx) = channels.lock().unwrap().get(&id) {
+        // Sending only fails when nobody is listening, which is fine.
+        let _ = tx.send(msg);
+    }
+}
+
+impl EventHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe_job(&self, job_id: i32) -> Subscription {
+        subscribe(&self.jobs, job_id)
+    }
+
+    pub fn subscribe_server(&self, server_id: i32) -> Subscription {
+        subscribe(&self.servers, server_id)
+    }
+
+    pub fn publisher(&self, job_id: i32, server_id: i32) -> Publisher {
+        Publisher {
+            hub: self.clone(),
+            job_id,
+            server_id,
+        }
+    }
+
+    /// Drops a job's channel, so its subscribers see the end of the stream.
+    pub fn close_job(&self, job_id: i32) {
+        self.jobs.lock().unwrap().remove(&job_id);
+    }
+
+    /// Drops a deleted server's channel, ending its subscribers' streams.
+    pub fn close_server(&self, server_id: i32) {
+        self.servers.lock().unwrap().remove(&server_id);
+    }
+}
+
+impl Publisher {
+    pub fn send(&self, msg: InstallEvent) {
+        send(&self.hub.jobs, self.job_id, msg.clone());
+        send(&self.hub.servers, self.server_id, msg);
+    }
+}
+
+impl Deref for Subscription {
+    type Target = broadcast::Receiver<InstallEvent>;
+
+    fn deref(&self) -> &Self::Target {
+        self.rx.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for Subscription {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.rx.as_mut().unwrap()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Let go of the receiver first, so it is no longer counted.
+        drop(self.rx.take());
+        let mut channels = self.channels.lock().unwrap();
+        let unused = channels
+            .get(&self.id)
+            .is_some_and(|tx| tx.receiver_count() == 0);
+        if unused {
+            channels.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::sync::broadcast::error::RecvError;
+
+    #[tokio::test]
+    async fn test_every_subscriber_gets_every_message() -> anyhow::Result<()> {
+        let hub = EventHub::new();
+        let mut first = hub.subscribe_job(1);
+        let mut second = hub.subscribe_job(1);
+        let mut server = hub.subscribe_server(10);
+        let mut other_server = hub.subscribe_server(11);
+
+        let one = InstallEvent::Output {
+            stream: OutputStream::Stdout,
+            line: "one".into(),
+        };
+        let two = InstallEvent::Output {
+            stream: OutputStream::Stderr,
+            line: "two".into(),
+        };
+        let publisher = hub.publisher(1, 10);
+        publisher.send(one.clone());
+        publisher.send(two.clone());
+
+        for rx in [&mut first, &mut second, &mut server] {
+            assert_eq!(one, rx.recv().await?);
+            assert_eq!(two, rx.recv().await?);
+        }
+        assert!(other_server.try_recv().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_close_job_ends_stream() {
+        let hub = EventHub::new();
+        let mut rx = hub.subscribe_job(1);
+        let publisher = hub.publisher(1, 10);
+
+        hub.close_job(1);
+        drop(publisher);
+
+        assert_eq!(Err(RecvError::Closed), rx.recv().await);
+    }
+
+    #[tokio::test]
+    async fn test_channels_dropped_with_last_subscriber() -> anyhow::Result<()> {
+        let hub = EventHub::new();
+        let first = hub.subscribe_server(10);
+        let mut second = hub.subscribe_server(10);
+        let job = hub.subscribe_job(1);
+        let publisher = hub.publisher(1, 10);
+
+        drop(first);
+        assert!(hub.servers.lock().unwrap().contains_key(&10));
+        drop(job);
+        assert!(hub.jobs.lock().unwrap().is_empty());
+
+        // A running job still reaches whoever subscribes afterwards.
+        let line = InstallEvent::Output {
+            stream: OutputStream::Stdout,
+            line: "one".into(),
+        };
+        publisher.send(line.clone());
+        assert_eq!(line, second.recv().await?);
+        drop(second);
+        assert!(hub.servers.lock().unwrap().is_empty());
+
+        let mut third = hub.subscribe_server(10);
+        publisher.send(line.clone());
+        assert_eq!(line, third.recv().await?);
+
+        hub.close_server(10);
+        assert_eq!(Err(RecvError::Closed), third.recv().await);
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_json() -> anyhow::Result<()> {
+        let event = InstallEvent::Output {
+            stream: OutputStream::Stdout,
+            line: "Loading Steam API...OK".into(),
+        };
+        assert_eq!(
+            r#"{"type":"output","stream":"stdout","line":"Loading Steam API...OK"}"#,
+            serde_json::to_string(&event)?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use rocket::{serde::json::Json, State};
+
+use super::ServiceError;
+use crate::{credentials::Credential, db, service::CredentialService};
+
+#[get("/")]
+pub async fn list_credentials(
+    credential_service: &State<CredentialService>,
+    db: db::Db,
+) -> Result<Json<Vec<Credential>>, ServiceError> {
+    credential_service
+        .list(&db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[post("/", data = "<credential>")]
+pub async fn save_credential(
+    credential: Json<Credential>,
+    credential_service: &State<CredentialService>,
+    db: db::Db,
+) -> Result<(), ServiceError> {
+    credential_service
+        .save(&credential, &db)
+        .await
+        .map_err(|e| e.into())
+}
+
+#[delete("/<login>")]
+pub async fn delete_credential(
+    login: &str,
+    credential_service: &State<CredentialService>,
+    db: db::Db,
+) -> Result<(), ServiceError> {
+    credential_service
+        .delete(login, &db)
+        .await
+        .map_err(|e| e.into())
+}
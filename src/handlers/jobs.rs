@@ -0,0 +1,88 @@
+use rocket::{response::stream::EventStream, serde::json::Json, State};
+use serde::Deserialize;
+
+use super::{event_stream, ServiceError};
+use crate::{
+    db,
+    events::EventHub,
+    jobs::{Job, JobAttempt},
+    service::{InstallService, JobService},
+};
+
+#[get("/<id>")]
+pub async fn get_job(
+    id: i32,
+    job_service: &State<JobService>,
+    db: db::Db,
+) -> Result<Json<Job>, ServiceError> {
+    job_service
+        .get_job(id, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[get("/<id>/attempts")]
+pub async fn job_attempts(
+    id: i32,
+    job_service: &State<JobService>,
+    db: db::Db,
+) -> Result<Json<Vec<JobAttempt>>, ServiceError> {
+    job_service
+        .list_attempts(id, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[get("/<id>/events")]
+pub async fn job_events(
+    id: i32,
+    job_service: &State<JobService>,
+    events: &State<EventHub>,
+    db: db::Db,
+) -> Result<Option<EventStream![]>, ServiceError> {
+    let job = match job_service.find_job(id, &db).await? {
+        Some(job) => job,
+        None => return Ok(None),
+    };
+    let rx = events.subscribe_job(id);
+    // Looked at again once subscribed, so that a job finishing in between
+    // still closes this stream.
+    if job.status.is_finished() || job_service.get_job(id, &db).await?.status.is_finished() {
+        events.close_job(id);
+    }
+    Ok(Some(event_stream(rx)))
+}
+
+#[derive(Deserialize)]
+pub struct GuardCode {
+    code: String,
+}
+
+#[post("/<id>/guard-code", data = "<guard_code>")]
+pub async fn submit_guard_code(
+    id: i32,
+    guard_code: Json<GuardCode>,
+    install_service: &State<InstallService>,
+    db: db::Db,
+) -> Result<Json<Job>, ServiceError> {
+    install_service
+        .submit_guard_code(id, &guard_code.code, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[post("/<id>/cancel")]
+pub async fn cancel_job(
+    id: i32,
+    install_service: &State<InstallService>,
+    db: db::Db,
+) -> Result<Json<Job>, ServiceError> {
+    install_service
+        .cancel(id, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
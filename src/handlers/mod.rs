@@ -1,11 +1,20 @@
 pub mod apps;
+pub mod credentials;
+pub mod jobs;
+pub mod pins;
+pub mod queue;
 pub mod server;
+pub mod steamcmd;
+pub mod storage;
 pub mod test;
+pub mod workshop;
 
 use std::sync::PoisonError;
 
-pub struct Tx(pub flume::Sender<String>);
-pub struct Rx(pub flume::Receiver<String>);
+use rocket::response::stream::{Event, EventStream};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::events::Subscription;
 
 #[derive(Debug, Responder)]
 #[response(status = 500, content_type = "json")]
@@ -27,3 +36,17 @@ impl<S> From<PoisonError<S>> for ServiceError {
         ServiceError(format!("{}", err))
     }
 }
+
+fn event_stream(mut rx: Subscription) -> EventStream![] {
+    EventStream! {
+        loop {
+            match rx.recv().await {
+                Ok(msg) => yield Event::json(&msg),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("event subscriber fell behind, skipped {} messages", skipped)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use rocket::{serde::json::Json, State};
+use serde::Deserialize;
+
+use super::ServiceError;
+use crate::{db, depots::DepotPin, service::ServerService};
+
+#[get("/<id>/pins")]
+pub async fn list_depot_pins(
+    id: i32,
+    server_service: &State<ServerService>,
+    db: db::Db,
+) -> Result<Json<Vec<DepotPin>>, ServiceError> {
+    server_service
+        .pins(id, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[derive(Deserialize)]
+pub struct NewDepotPin {
+    manifest_id: String,
+}
+
+/// Pins a depot to a manifest. The server is then installed with exactly the
+/// pinned manifests and skipped by scheduled updates until every pin is gone.
+#[put("/<id>/pins/<depot_id>", data = "<pin>")]
+pub async fn pin_depot(
+    id: i32,
+    depot_id: i32,
+    pin: Json<NewDepotPin>,
+    server_service: &State<ServerService>,
+    db: db::Db,
+) -> Result<Json<Vec<DepotPin>>, ServiceError> {
+    server_service
+        .pin(id, depot_id, &pin.manifest_id, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[delete("/<id>/pins/<depot_id>")]
+pub async fn unpin_depot(
+    id: i32,
+    depot_id: i32,
+    server_service: &State<ServerService>,
+    db: db::Db,
+) -> Result<Json<Vec<DepotPin>>, ServiceError> {
+    server_service
+        .unpin(id, depot_id, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
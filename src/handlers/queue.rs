@@ -0,0 +1,39 @@
+use rocket::{serde::json::Json, State};
+use serde::Deserialize;
+
+use super::ServiceError;
+use crate::{
+    db,
+    service::{InstallService, QueuedJob},
+};
+
+#[get("/queue")]
+pub fn install_queue(install_service: &State<InstallService>) -> Json<Vec<QueuedJob>> {
+    Json(install_service.queued())
+}
+
+#[derive(Deserialize)]
+pub struct QueueChange {
+    #[serde(default)]
+    priority: Option<i32>,
+    /// Applied after any new priority.
+    #[serde(default)]
+    move_to_front: bool,
+}
+
+/// Reorders a queued job, returning the queue as it now stands.
+#[patch("/queue/<job_id>", data = "<change>")]
+pub async fn change_queued_job(
+    job_id: i32,
+    change: Json<QueueChange>,
+    install_service: &State<InstallService>,
+    db: db::Db,
+) -> Result<Json<Vec<QueuedJob>>, ServiceError> {
+    if let Some(priority) = change.priority {
+        install_service.set_priority(job_id, priority, &db).await?;
+    }
+    if change.move_to_front {
+        install_service.move_to_front(job_id, &db).await?;
+    }
+    Ok(Json(install_service.queued()))
+}
@@ -1,14 +1,15 @@
-use super::{Rx, ServiceError, Tx};
+use super::{event_stream, ServiceError};
 use crate::{
+    app_manifest::InstalledBuild,
     db,
-    install::{InstallQueueTx, Server},
-    service::ServerService,
-};
-use rocket::{
-    response::stream::{Event, EventStream},
-    serde::json::Json,
-    State,
+    events::EventHub,
+    install::Server,
+    jobs::{Job, JobKind},
+    schedule::UpdateSchedule,
+    service::{InstallService, JobService, ServerService},
+    snapshot::Snapshot,
 };
+use rocket::{response::stream::EventStream, serde::json::Json, State};
 
 #[get("/")]
 pub async fn list_servers(
@@ -16,7 +17,7 @@ pub async fn list_servers(
     db: db::Db,
 ) -> Result<Json<Vec<Server>>, ServiceError> {
     server_service
-        .list_servers(db)
+        .list_servers(&db)
         .await
         .map(Json)
         .map_err(|e| e.into())
@@ -30,7 +31,7 @@ pub async fn create_server(
     db: db::Db,
 ) -> Result<(), ServiceError> {
     let service = server_service;
-    service.new_server(&server, db).await?;
+    service.new_server(&server, &db).await?;
     Ok(())
 }
 
@@ -39,11 +40,9 @@ pub async fn get_server(
     id: i32,
     server_service: &State<ServerService>,
     db: db::Db,
-    tx: &State<Tx>,
 ) -> Result<Json<Server>, ServiceError> {
-    tx.0.send_async(String::from("got")).await.unwrap();
     server_service
-        .get_server(id, db)
+        .get_server(id, &db)
         .await
         .map(Json)
         .map_err(|e| e.into())
@@ -53,36 +52,133 @@ pub async fn get_server(
 pub async fn delete(
     id: i32,
     server_service: &State<ServerService>,
+    events: &State<EventHub>,
     db: db::Db,
 ) -> Result<(), ServiceError> {
-    server_service.delete(id, db).await.map_err(|e| e.into())
+    server_service.delete(id, &db).await?;
+    events.close_server(id);
+    Ok(())
+}
+
+async fn enqueue(
+    id: i32,
+    kind: JobKind,
+    server_service: &ServerService,
+    install_service: &InstallService,
+    db: db::Db,
+) -> Result<Json<Job>, ServiceError> {
+    let server = server_service.get_server(id, &db).await?;
+    let job = install_service.enqueue(server, kind, &db).await?;
+
+    Ok(Json(job))
 }
 
+/// Installs the server, or brings it up to date without checking files that
+/// did not change.
 #[post("/install/<id>")]
 pub async fn install(
     id: i32,
     server_service: &State<ServerService>,
-    install_queue: &State<InstallQueueTx>,
+    install_service: &State<InstallService>,
     db: db::Db,
-) -> Result<(), ServiceError> {
-    let server = server_service.get_server(id, db).await?;
-    let queue = &install_queue.0;
+) -> Result<Json<Job>, ServiceError> {
+    enqueue(id, JobKind::Update, server_service, install_service, db).await
+}
 
-    queue
-        .send(server)
-        .map_err(|e| ServiceError(format!("{}", e)))?;
+#[post("/<id>/validate")]
+pub async fn validate(
+    id: i32,
+    server_service: &State<ServerService>,
+    install_service: &State<InstallService>,
+    db: db::Db,
+) -> Result<Json<Job>, ServiceError> {
+    enqueue(id, JobKind::Validate, server_service, install_service, db).await
+}
 
-    Ok(())
+#[post("/<id>/verify")]
+pub async fn verify(
+    id: i32,
+    server_service: &State<ServerService>,
+    install_service: &State<InstallService>,
+    db: db::Db,
+) -> Result<Json<Job>, ServiceError> {
+    enqueue(id, JobKind::Verify, server_service, install_service, db).await
+}
+
+/// Sets when the server is updated automatically.
+#[put("/<id>/schedule", data = "<schedule>")]
+pub async fn set_update_schedule(
+    id: i32,
+    schedule: Json<UpdateSchedule>,
+    server_service: &State<ServerService>,
+    db: db::Db,
+) -> Result<Json<Server>, ServiceError> {
+    server_service
+        .set_update_schedule(id, schedule.into_inner(), &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+/// What steamcmd says is installed, or not found before the first install.
+#[get("/<id>/installed")]
+pub async fn installed_build(
+    id: i32,
+    server_service: &State<ServerService>,
+    db: db::Db,
+) -> Result<Option<Json<InstalledBuild>>, ServiceError> {
+    server_service
+        .installed_build(id, &db)
+        .await
+        .map(|build| build.map(Json))
+        .map_err(|e| e.into())
+}
+
+/// The snapshots taken before the server's updates, newest first.
+#[get("/<id>/snapshots")]
+pub async fn list_snapshots(
+    id: i32,
+    server_service: &State<ServerService>,
+    db: db::Db,
+) -> Result<Json<Vec<Snapshot>>, ServiceError> {
+    server_service
+        .snapshots(id, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+/// Restores the server's files from its newest snapshot, or from the one
+/// given as `?snapshot=<id>`.
+#[post("/<id>/rollback?<snapshot>")]
+pub async fn rollback(
+    id: i32,
+    snapshot: Option<i32>,
+    install_service: &State<InstallService>,
+    db: db::Db,
+) -> Result<Json<Snapshot>, ServiceError> {
+    install_service
+        .rollback(id, snapshot, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[get("/<id>/jobs")]
+pub async fn server_jobs(
+    id: i32,
+    job_service: &State<JobService>,
+    db: db::Db,
+) -> Result<Json<Vec<Job>>, ServiceError> {
+    job_service
+        .list_jobs(id, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
 }
 
-#[get("/install/events")]
-pub fn install_events(rx: &State<Rx>) -> EventStream![Event + '_] {
-    let receiver = &rx.0;
-    debug!("events called");
-    EventStream! {
-        while let Ok(msg) = receiver.recv_async().await {
-            debug!("received: {}", msg);
-            yield Event::data(msg);
-        }
-    }
+#[get("/<id>/install/events")]
+pub fn install_events(id: i32, events: &State<EventHub>) -> EventStream![] {
+    debug!("events called for server {}", id);
+    event_stream(events.subscribe_server(id))
 }
@@ -0,0 +1,32 @@
+use rocket::{serde::json::Json, State};
+
+use super::ServiceError;
+use crate::{
+    db,
+    service::{SteamCmdService, SteamCmdStatus},
+    steamcmd::SteamCmdInstall,
+};
+
+#[get("/")]
+pub async fn steamcmd_status(
+    steamcmd_service: &State<SteamCmdService>,
+    db: db::Db,
+) -> Result<Json<SteamCmdStatus>, ServiceError> {
+    steamcmd_service
+        .status(&db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[post("/install")]
+pub async fn install_steamcmd(
+    steamcmd_service: &State<SteamCmdService>,
+    db: db::Db,
+) -> Result<Json<SteamCmdInstall>, ServiceError> {
+    steamcmd_service
+        .install(&db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
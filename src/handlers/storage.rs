@@ -0,0 +1,12 @@
+use rocket::{serde::json::Json, State};
+
+use super::ServiceError;
+use crate::{disk::DiskUsage, service::StorageService};
+
+/// Free and used space on the disk holding each base directory.
+#[get("/")]
+pub fn storage_usage(
+    storage_service: &State<StorageService>,
+) -> Result<Json<Vec<DiskUsage>>, ServiceError> {
+    storage_service.usage().map(Json).map_err(|e| e.into())
+}
@@ -0,0 +1,113 @@
+use rocket::{serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+
+use super::ServiceError;
+use crate::{
+    db,
+    service::{ServerService, WorkshopService},
+    workshop::{CollectionResponse, WorkshopItem},
+};
+
+#[derive(Deserialize)]
+pub struct NewWorkshopItem {
+    item_id: i64,
+}
+
+#[get("/<id>/workshop")]
+pub async fn list_workshop_items(
+    id: i32,
+    workshop_service: &State<WorkshopService>,
+    db: db::Db,
+) -> Result<Json<Vec<WorkshopItem>>, ServiceError> {
+    workshop_service
+        .list(id, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+/// Adds an item, which is downloaded by the server's next update or validate.
+#[post("/<id>/workshop", data = "<item>")]
+pub async fn add_workshop_item(
+    id: i32,
+    item: Json<NewWorkshopItem>,
+    workshop_service: &State<WorkshopService>,
+    db: db::Db,
+) -> Result<Json<WorkshopItem>, ServiceError> {
+    workshop_service
+        .add(id, item.item_id, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[delete("/<id>/workshop/<item_id>")]
+pub async fn remove_workshop_item(
+    id: i32,
+    item_id: i64,
+    workshop_service: &State<WorkshopService>,
+    db: db::Db,
+) -> Result<(), ServiceError> {
+    workshop_service
+        .remove(id, item_id, &db)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Moves the listed items to the front of the load order, in the order given.
+#[put("/<id>/workshop/order", data = "<item_ids>")]
+pub async fn order_workshop_items(
+    id: i32,
+    item_ids: Json<Vec<i64>>,
+    workshop_service: &State<WorkshopService>,
+    db: db::Db,
+) -> Result<Json<Vec<WorkshopItem>>, ServiceError> {
+    workshop_service
+        .reorder(id, item_ids.into_inner(), &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[derive(Deserialize)]
+pub struct ImportCollection {
+    collection_id: i64,
+    /// A `GetCollectionDetails` response to import from instead of asking
+    /// Steam.
+    #[serde(default)]
+    details: Option<CollectionResponse>,
+}
+
+#[post("/<id>/workshop/collection", data = "<import>")]
+pub async fn import_workshop_collection(
+    id: i32,
+    import: Json<ImportCollection>,
+    workshop_service: &State<WorkshopService>,
+    db: db::Db,
+) -> Result<Json<Vec<WorkshopItem>>, ServiceError> {
+    let import = import.into_inner();
+    workshop_service
+        .import_collection(id, import.collection_id, import.details, &db)
+        .await
+        .map(Json)
+        .map_err(|e| e.into())
+}
+
+#[derive(Serialize)]
+pub struct LaunchArgs {
+    args: Vec<String>,
+}
+
+/// The arguments to start the server with so that it loads its mods in
+/// order.
+#[get("/<id>/launch-args")]
+pub async fn launch_args(
+    id: i32,
+    server_service: &State<ServerService>,
+    workshop_service: &State<WorkshopService>,
+    db: db::Db,
+) -> Result<Json<LaunchArgs>, ServiceError> {
+    let server = server_service.get_server(id, &db).await?;
+    let args = workshop_service.launch_args(&server, &db).await?;
+    Ok(Json(LaunchArgs { args }))
+}
@@ -1,12 +1,33 @@
-use std::{path::Path, process::Stdio};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    process::Stdio,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
+use crate::app_info::AppInfo;
+use crate::credentials::{Credential, ANONYMOUS};
+use crate::depots::{DepotDownload, DepotPin};
+use crate::events::{InstallEvent, OutputStream, Publisher};
+use crate::jobs::JobKind;
+use crate::progress::ProgressTracker;
+use crate::runscript::{Runscript, SteamCommand};
+use crate::schedule::UpdatePolicy;
 use crate::schema::*;
+use crate::steam_guard::{self, SteamGuard};
+use crate::workshop::{ItemDownload, ModLink};
 use anyhow::Result;
-use diesel::Queryable;
-use log::debug;
+use chrono::NaiveDateTime;
+use diesel::{sql_types::Text, Queryable};
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncBufReadExt, BufReader, Error};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, Error};
 use tokio::process::{Child, Command};
+use tokio::sync::{Mutex as AsyncMutex, Notify, OwnedMutexGuard};
+use tokio::time::Instant as TokioInstant;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Queryable, Insertable)]
 pub struct Server {
@@ -14,10 +35,110 @@ pub struct Server {
     pub name: String,
     pub login: String,
     pub install_dir: String,
+    #[serde(default)]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub branch_password: Option<String>,
+    #[serde(default)]
+    pub platform: Option<Platform>,
+    #[serde(skip_deserializing)]
+    pub installed_platform: Option<Platform>,
+    /// The app Workshop items are published under, when it is not the
+    /// server's own, e.g. Arma 3 rather than its dedicated server.
+    #[serde(default)]
+    pub workshop_app_id: Option<i32>,
+    /// Where Workshop items are put, relative to `install_dir`. Items are only
+    /// left in steamcmd's own download folder when this is not set.
+    #[serde(default)]
+    pub mods_dir: Option<String>,
+    #[serde(default)]
+    pub mods_link: ModLink,
+    /// The build in the server's app manifest when it was last installed or
+    /// checked for updates.
+    #[serde(skip_deserializing)]
+    pub installed_build_id: Option<i64>,
+    /// The build last seen released on the server's branch.
+    #[serde(skip_deserializing)]
+    pub latest_build_id: Option<i64>,
+    #[serde(skip_deserializing)]
+    pub update_available: bool,
+    #[serde(skip_deserializing)]
+    pub update_checked_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub update_policy: UpdatePolicy,
+    /// The time of day or cron expression `update_policy` runs at.
+    #[serde(default)]
+    pub update_schedule: Option<String>,
+    #[serde(default)]
+    pub maintenance_window: Option<String>,
+    #[serde(skip_deserializing)]
+    pub next_update_at: Option<NaiveDateTime>,
+    /// What the latest build takes up on the server's platform, going by
+    /// the last update check.
+    #[serde(skip_deserializing)]
+    pub latest_size: Option<i64>,
 }
 
-pub struct InstallQueueTx(pub flume::Sender<Server>);
-pub struct InstallQueueRx(pub flume::Receiver<Server>);
+/// The platform whose depots steamcmd downloads.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum Platform {
+    Linux,
+    Windows,
+    Macos,
+}
+
+impl Platform {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Platform::Linux => "linux",
+            Platform::Windows => "windows",
+            Platform::Macos => "macos",
+        }
+    }
+
+    /// What steamcmd downloads when no platform is forced.
+    pub fn host() -> Self {
+        if cfg!(target_os = "windows") {
+            Platform::Windows
+        } else if cfg!(target_os = "macos") {
+            Platform::Macos
+        } else {
+            Platform::Linux
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linux" => Ok(Platform::Linux),
+            "windows" => Ok(Platform::Windows),
+            "macos" => Ok(Platform::Macos),
+            other => Err(anyhow::anyhow!("unknown platform: {}", other)),
+        }
+    }
+}
+
+text_column!(Platform);
+
+#[derive(Debug, Clone)]
+pub struct InstallRequest {
+    pub job_id: i32,
+    pub kind: JobKind,
+    /// Requests with higher priorities are picked up first.
+    pub priority: i32,
+    pub server: Server,
+}
 
 impl Server {
     pub fn new(id: i32, name: &str, login: &str, install_dir: &str) -> Self {
@@ -26,130 +147,1411 @@ impl Server {
             name: name.into(),
             login: login.into(),
             install_dir: install_dir.into(),
+            branch: None,
+            branch_password: None,
+            platform: None,
+            installed_platform: None,
+            workshop_app_id: None,
+            mods_dir: None,
+            mods_link: ModLink::default(),
+            installed_build_id: None,
+            latest_build_id: None,
+            update_available: false,
+            update_checked_at: None,
+            update_policy: UpdatePolicy::default(),
+            update_schedule: None,
+            maintenance_window: None,
+            next_update_at: None,
+            latest_size: None,
+        }
+    }
+
+    pub fn workshop_app(&self) -> i32 {
+        self.workshop_app_id.unwrap_or(self.id)
+    }
+
+    /// The platform an install of this server downloads.
+    pub fn target_platform(&self) -> Platform {
+        self.platform.unwrap_or_else(Platform::host)
+    }
+
+    /// The beta branch to install, or `None` for the default public branch.
+    pub fn beta(&self) -> Option<&str> {
+        self.branch
+            .as_deref()
+            .map(str::trim)
+            .filter(|b| !b.is_empty() && *b != "public")
+    }
+
+    /// `app_update` for the server's branch.
+    fn app_update(&self, validate: bool) -> SteamCommand {
+        let beta = self.beta().map(String::from);
+        SteamCommand::AppUpdate {
+            app_id: self.id,
+            beta_password: beta.as_ref().and(self.branch_password.clone()),
+            beta,
+            validate,
         }
     }
 }
 
+/// Reads the `install state` line that `+app_status` prints, e.g.
+/// ` - install state: Fully Installed,`, into whether the files are intact.
+///
+/// This is steamcmd's own record of the install, kept up to date by every
+/// update and validate, rather than a fresh hash of each file.
+pub fn files_intact(line: &str) -> Option<bool> {
+    let (_, state) = line.split_once("install state:")?;
+    let damaged = [
+        "Update Required",
+        "Files Missing",
+        "Files Corrupt",
+        "Uninstalled",
+    ]
+    .iter()
+    .any(|flag| state.contains(flag));
+    Some(state.contains("Fully Installed") && !damaged)
+}
+
+/// Whether a line is steamcmd saying an app update went through, e.g.
+/// `Success! App '740' fully installed.`, which clears the errors printed on
+/// the way there.
+fn app_installed(line: &str) -> bool {
+    line.trim_start().starts_with("Success! App")
+}
+
+/// How a steamcmd run that exited cleanly went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finished {
+    pub exit_code: i32,
+    /// Only known for verify jobs.
+    pub files_intact: Option<bool>,
+    /// Depots fetched for a pinned server, still to be moved into place.
+    pub depots: Vec<DepotDownload>,
+}
+
+/// One lock per server, so the same install directory is never updated by
+/// two jobs at once.
+#[derive(Clone, Default)]
+pub struct ServerLocks {
+    locks: Arc<Mutex<HashMap<i32, Arc<AsyncMutex<()>>>>>,
+}
+
+impl ServerLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a server's lock only when no job holds it.
+    pub fn try_lock(&self, server_id: i32) -> Option<OwnedMutexGuard<()>> {
+        self.get(server_id).try_lock_owned().ok()
+    }
+
+    fn get(&self, server_id: i32) -> Arc<AsyncMutex<()>> {
+        self.locks
+            .lock()
+            .unwrap()
+            .entry(server_id)
+            .or_default()
+            .clone()
+    }
+}
+
 pub trait ServerStorage {
     fn save(&self, server: &Server) -> Result<()>;
     fn load(&self, server_id: i32) -> Result<Server>;
     fn list(&self) -> Result<Vec<Server>>;
 }
 
-pub struct SteamCommand<'a> {
-    command: &'a str,
-    args: &'a [&'a str],
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum InstallError {
+    NoSubscription,
+    InvalidPlatform,
+    DiskWriteFailure,
+    /// Too little free space to start the install, found before running
+    /// steamcmd.
+    InsufficientSpace,
+    MissingConfiguration,
+    MissingCredentials,
+    LoginRejected,
+    /// The install directory could not be copied before an update, so the
+    /// update was not started.
+    SnapshotFailed,
+    /// steamcmd went quiet for longer than the output timeout.
+    OutputTimeout,
+    /// steamcmd ran for longer than the overall timeout.
+    InstallTimeout,
+    /// steamcmd gave up on an update part way, e.g. `state is 0x402`.
+    UpdateInterrupted,
+    /// Steam did not answer in time.
+    Timeout,
+    Cancelled,
+    Unknown,
 }
+
+impl InstallError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstallError::NoSubscription => "no_subscription",
+            InstallError::InvalidPlatform => "invalid_platform",
+            InstallError::DiskWriteFailure => "disk_write_failure",
+            InstallError::InsufficientSpace => "insufficient_space",
+            InstallError::MissingConfiguration => "missing_configuration",
+            InstallError::MissingCredentials => "missing_credentials",
+            InstallError::LoginRejected => "login_rejected",
+            InstallError::SnapshotFailed => "snapshot_failed",
+            InstallError::OutputTimeout => "output_timeout",
+            InstallError::InstallTimeout => "install_timeout",
+            InstallError::UpdateInterrupted => "update_interrupted",
+            InstallError::Timeout => "timeout",
+            InstallError::Cancelled => "cancelled",
+            InstallError::Unknown => "unknown",
+        }
+    }
+
+    /// Whether running steamcmd again could well get past the error.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            InstallError::UpdateInterrupted | InstallError::Timeout
+        )
+    }
+
+    /// Recognises the failures steamcmd reports in its output, e.g.
+    /// `ERROR! Failed to install app '740' (No subscription)`.
+    pub fn from_line(line: &str) -> Option<Self> {
+        // Only the lines steamcmd prints for a failure count as timeouts, as
+        // it also mentions the ones it recovers from on its own.
+        let failed = ["ERROR!", "Error!"]
+            .iter()
+            .any(|prefix| line.trim_start().starts_with(prefix))
+            || line.contains("FAILED login");
+        if line.contains("No subscription") {
+            Some(InstallError::NoSubscription)
+        } else if line.contains("Invalid platform") {
+            Some(InstallError::InvalidPlatform)
+        } else if line.contains("Disk write failure") {
+            Some(InstallError::DiskWriteFailure)
+        } else if line.contains("Missing configuration") {
+            Some(InstallError::MissingConfiguration)
+        } else if ["0x402", "0x602"].iter().any(|state| {
+            line.contains(&format!("state is {}", state))
+                || line.contains(&format!("Update state ({})", state))
+        }) {
+            Some(InstallError::UpdateInterrupted)
+        } else if [
+            "result code Timeout",
+            "result code No Connection",
+            "Connection timeout",
+            "(Timeout)",
+            "timed out",
+        ]
+        .iter()
+        .any(|timeout| failed && line.contains(timeout))
+        {
+            Some(InstallError::Timeout)
+        } else if line.contains("FAILED login")
+            || line.contains("Login Failure")
+            || line.contains("Invalid Password")
+        {
+            Some(InstallError::LoginRejected)
+        } else if line.trim_start().starts_with("ERROR!") {
+            Some(InstallError::Unknown)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for InstallError {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "no_subscription" => Ok(InstallError::NoSubscription),
+            "invalid_platform" => Ok(InstallError::InvalidPlatform),
+            "disk_write_failure" => Ok(InstallError::DiskWriteFailure),
+            "insufficient_space" => Ok(InstallError::InsufficientSpace),
+            "missing_configuration" => Ok(InstallError::MissingConfiguration),
+            "missing_credentials" => Ok(InstallError::MissingCredentials),
+            "login_rejected" => Ok(InstallError::LoginRejected),
+            "snapshot_failed" => Ok(InstallError::SnapshotFailed),
+            "output_timeout" => Ok(InstallError::OutputTimeout),
+            "install_timeout" => Ok(InstallError::InstallTimeout),
+            "update_interrupted" => Ok(InstallError::UpdateInterrupted),
+            "timeout" => Ok(InstallError::Timeout),
+            "cancelled" => Ok(InstallError::Cancelled),
+            "unknown" => Ok(InstallError::Unknown),
+            other => Err(anyhow::anyhow!("unknown install error: {}", other)),
+        }
+    }
+}
+
+text_column!(InstallError);
+
+/// A steamcmd run that failed, along with the line or exit status that gave
+/// it away.
+#[derive(Debug)]
+pub struct InstallFailure {
+    pub error: InstallError,
+    pub message: String,
+    pub exit_code: Option<i32>,
+}
+
+impl fmt::Display for InstallFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for InstallFailure {}
+
+impl InstallFailure {
+    pub fn cancelled() -> Self {
+        InstallFailure {
+            error: InstallError::Cancelled,
+            message: String::from("install was cancelled"),
+            exit_code: None,
+        }
+    }
+}
+
+/// The script steamcmd runs for a job of the given kind on a server.
+pub fn runscript(
+    base_dir: &str,
+    server: &Server,
+    kind: JobKind,
+    credential: Option<&Credential>,
+    workshop_items: &[i64],
+    pins: &[DepotPin],
+) -> Runscript {
+    let install_dir = Path::new(base_dir)
+        .join(server.install_dir.as_str())
+        .display()
+        .to_string();
+    let mut commands = vec![];
+    if let Some(platform) = server.platform {
+        commands.push(SteamCommand::ForcePlatformType(platform));
+    }
+    commands.push(SteamCommand::ForceInstallDir(install_dir));
+    commands.push(match credential {
+        Some(c) => SteamCommand::Login {
+            username: c.login.clone(),
+            password: Some(c.password.clone()),
+        },
+        None => SteamCommand::Login {
+            username: ANONYMOUS.into(),
+            password: None,
+        },
+    });
+    match kind {
+        // download_depot always checks the files it fetches, so validating a
+        // pinned server is the same as updating it. The pins only cover some
+        // of the app's depots, so a server that was never installed gets the
+        // rest from app_update first.
+        JobKind::Update | JobKind::Validate if !pins.is_empty() => {
+            if server.installed_platform.is_none() {
+                commands.push(server.app_update(kind == JobKind::Validate));
+            }
+            commands.extend(pins.iter().map(|pin| SteamCommand::DownloadDepot {
+                app_id: server.id,
+                depot_id: pin.depot_id,
+                manifest_id: pin.manifest_id.clone(),
+            }))
+        }
+        JobKind::Update => commands.push(server.app_update(false)),
+        JobKind::Validate => commands.push(server.app_update(true)),
+        JobKind::Verify => commands.push(SteamCommand::AppStatus(server.id)),
+    }
+    if kind.writes_files() {
+        commands.extend(
+            workshop_items
+                .iter()
+                .map(|&item_id| SteamCommand::WorkshopDownloadItem {
+                    app_id: server.workshop_app(),
+                    item_id,
+                    validate: kind == JobKind::Validate,
+                }),
+        );
+    }
+    Runscript::new(commands)
+}
+
+#[derive(Clone)]
 pub struct Client {
     steamd_cmd: String,
+    timeouts: Timeouts,
+}
+
+/// How long a steamcmd run may take before it is presumed stuck and killed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// For the whole run, including any wait for a Steam Guard code.
+    pub overall: Option<Duration>,
+    /// Without steamcmd printing anything, not counting time spent waiting on
+    /// an operator for a Steam Guard code.
+    pub output: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Takes `0` to mean no limit.
+    pub fn from_secs(overall: u64, output: u64) -> Self {
+        let limit = |secs| match secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        Timeouts {
+            overall: limit(overall),
+            output: limit(output),
+        }
+    }
+
+    /// The soonest limit the run can hit, and the error it fails with then.
+    fn next(
+        &self,
+        started: TokioInstant,
+        last_output: TokioInstant,
+        awaiting_code: bool,
+    ) -> Option<(TokioInstant, InstallError)> {
+        let overall = self
+            .overall
+            .map(|limit| (started + limit, InstallError::InstallTimeout));
+        let output = match awaiting_code {
+            true => None,
+            false => self
+                .output
+                .map(|limit| (last_output + limit, InstallError::OutputTimeout)),
+        };
+        overall.into_iter().chain(output).min_by_key(|(at, _)| *at)
+    }
+
+    fn expired(&self, error: InstallError) -> InstallFailure {
+        let limit = match error {
+            InstallError::OutputTimeout => self.output,
+            _ => self.overall,
+        };
+        let secs = limit.unwrap_or_default().as_secs();
+        InstallFailure {
+            error,
+            message: match error {
+                InstallError::OutputTimeout => {
+                    format!("steamcmd printed nothing for {}s and was stopped", secs)
+                }
+                _ => format!("steamcmd ran for more than {}s and was stopped", secs),
+            },
+            exit_code: None,
+        }
+    }
 }
 
 impl Client {
     pub fn new(steamd_cmd: &str) -> Self {
         Self {
             steamd_cmd: steamd_cmd.into(),
+            timeouts: Timeouts::default(),
         }
     }
 
-    fn run(&self, commands: &Vec<SteamCommand>) -> anyhow::Result<Child> {
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Looks up what Steam currently has released for an app.
+    ///
+    /// This logs in anonymously, which every dedicated server's app info is
+    /// visible to, so a background check never needs a Steam Guard code.
+    pub async fn app_info(&self, app_id: i32) -> anyhow::Result<AppInfo> {
+        let script = Runscript::new(vec![
+            SteamCommand::Login {
+                username: ANONYMOUS.into(),
+                password: None,
+            },
+            SteamCommand::AppInfoUpdate,
+            SteamCommand::AppInfoPrint(app_id),
+        ]);
+        let file = script.write()?;
+        let mut proc = self.run(file.path())?;
+        drop(proc.stdin.take());
+        let mut stdout = proc.stdout.take().expect("stdout is piped");
+        let mut stderr = proc.stderr.take().expect("stderr is piped");
+        let mut output = String::new();
+        let mut errors = String::new();
+        let finished = async {
+            tokio::try_join!(
+                stdout.read_to_string(&mut output),
+                stderr.read_to_string(&mut errors)
+            )?;
+            proc.wait().await
+        };
+        let status = match self.timeouts.overall {
+            Some(limit) => tokio::time::timeout(limit, finished).await,
+            None => Ok(finished.await),
+        };
+        let status = match status {
+            Ok(status) => status?,
+            Err(_) => {
+                kill_tree(&mut proc).await;
+                return Err(self.timeouts.expired(InstallError::InstallTimeout).into());
+            }
+        };
+        debug!("steamcmd app_info_print {} exited with {}", app_id, status);
+        AppInfo::from_output(&output, app_id)
+    }
+
+    fn run(&self, script: &Path) -> anyhow::Result<Child> {
         let mut p = Command::new(&self.steamd_cmd);
         p.kill_on_drop(true);
-        p.stdout(Stdio::piped()).stderr(Stdio::piped());
-        for c in commands {
-            p.arg(format!("{} {}", c.command, c.args.join(" ")));
+        p.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Give steamcmd its own process group so that cancelling can take down
+        // the binary that steamcmd.sh starts as well as the script itself.
+        unsafe {
+            p.pre_exec(|| {
+                if libc::setpgid(0, 0) == 0 {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::last_os_error())
+                }
+            });
         }
+        p.arg("+runscript").arg(script);
 
-        //debug!("{:?}", p.);
         let output = p.spawn()?;
-        //let handle = p.spawn()?;
 
         Ok(output)
     }
 
     pub async fn install(
         &self,
-        base_dir: &str,
-        server: &Server,
-        sender: &flume::Sender<String>,
-    ) -> anyhow::Result<()> {
-        let path = Path::new(base_dir)
-            .join(server.install_dir.as_str())
-            .display()
-            .to_string();
-
-        let install_dir = [path.as_str()];
-        let app_update = [&server.id.to_string(), "validate"];
-        let commands = vec![
-            SteamCommand {
-                command: "+force_install_dir",
-                args: &install_dir,
-            },
-            SteamCommand {
-                command: "+login",
-                args: &["anonymous"],
-            },
-            SteamCommand {
-                command: "+app_update",
-                args: &app_update,
-            },
-            SteamCommand {
-                command: "+exit",
-                args: &[],
-            },
-        ];
-        debug!("running steamcmd install for application {}", server.name);
-        let proc = self.run(&commands)?;
+        script: &Runscript,
+        kind: JobKind,
+        guard: &mut SteamGuard,
+        sender: &Publisher,
+        cancel: &Notify,
+        downloads: &mut Vec<ItemDownload>,
+    ) -> anyhow::Result<Finished> {
+        // Kept until steamcmd exits, which removes the file.
+        let file = script.write()?;
+        debug!("running steamcmd {} from {}", kind, file.path().display());
+        let mut proc = self.run(file.path())?;
 
-        let output = proc
+        let mut stdin = proc
+            .stdin
+            .take()
+            .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Could not capture stdin"))?;
+        let stdout = proc
             .stdout
+            .take()
             .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Could not capture stdout"))?;
+        let stderr = proc
+            .stderr
+            .take()
+            .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Could not capture stderr"))?;
+        let mut stdout = OutputLines::new(stdout);
+        let mut stderr = OutputLines::new(stderr);
+        let (mut stdout_open, mut stderr_open) = (true, true);
 
-        // let reader =
-        //     BufReader::new(proc.stdout.ok_or_else(|| {
-        //         Error::new(std::io::ErrorKind::Other, "Could not capture stdout")
-        //     })?);
-        //let writer = BufWriter::new(sender);
-        //let lines = reader.lines().filter_map(|line| line.ok());
-        let mut reader = BufReader::new(output).lines();
-
-        // tokio::spawn(async {
-        //     let status = proc.wait().await.expect("couldn't process exit status");
-        //     debug!("child status was {}", status);
-        // });
-
-        while let Some(l) = reader.next_line().await? {
-            log::debug!("line: {}", l);
-            sender.send_async(l).await?;
-        }
-        // for line in reader.lines() {
-        //     match line {
-        //         Ok(l) => {
-        //             log::debug!("line: {}", l);
-        //             sender.send_async(l).await?
-        //         }
-        //         Err(e) => return Err(anyhow::anyhow!(e)),
-        //     }
-        // }
+        let mut tracker = ProgressTracker::new();
+        let mut failure: Option<(InstallError, String)> = None;
+        let mut awaiting_code = false;
+        let mut intact = None;
+        let mut depots = vec![];
+        let cancelled = cancel.notified();
+        tokio::pin!(cancelled);
+        let started = TokioInstant::now();
+        let mut last_output = started;
+        while stdout_open || stderr_open {
+            let watchdog = self.timeouts.next(started, last_output, awaiting_code);
+            let deadline = watchdog.map_or(started, |(at, _)| at);
+            let (stream, line) = tokio::select! {
+                line = stdout.next_line(), if stdout_open => (OutputStream::Stdout, line?),
+                line = stderr.next_line(), if stderr_open => (OutputStream::Stderr, line?),
+                code = guard.operator_code(), if awaiting_code => {
+                    awaiting_code = false;
+                    if let Some(code) = code {
+                        debug!("passing operator's Steam Guard code to steamcmd");
+                        write_code(&mut stdin, &code).await?;
+                        guard.set_waiting(false);
+                        last_output = TokioInstant::now();
+                    }
+                    continue;
+                }
+                _ = tokio::time::sleep_until(deadline), if watchdog.is_some() => {
+                    let error = watchdog.map_or(InstallError::Unknown, |(_, error)| error);
+                    warn!("steamcmd {} hit its {} limit", kind, error);
+                    kill_tree(&mut proc).await;
+                    return Err(self.timeouts.expired(error).into());
+                }
+                _ = &mut cancelled => {
+                    debug!("cancelling steamcmd {}", kind);
+                    kill_tree(&mut proc).await;
+                    return Err(InstallFailure::cancelled().into());
+                }
+            };
+            last_output = TokioInstant::now();
+            let l = match line {
+                Some(l) => l,
+                None => {
+                    match stream {
+                        OutputStream::Stdout => stdout_open = false,
+                        OutputStream::Stderr => stderr_open = false,
+                    }
+                    continue;
+                }
+            };
+            log::debug!("{:?}: {}", stream, l);
 
-        Ok(())
+            if let Some(error) = InstallError::from_line(&l) {
+                // Keep the first error that says what actually went wrong.
+                let replace = match &failure {
+                    None => true,
+                    Some((InstallError::Unknown, _)) => error != InstallError::Unknown,
+                    Some(_) => false,
+                };
+                if replace {
+                    failure = Some((error, l.clone()));
+                }
+            } else if app_installed(&l) {
+                failure = None;
+            }
+            if kind == JobKind::Verify {
+                intact = files_intact(&l).or(intact);
+            }
+            if let Some(download) = ItemDownload::from_line(&l) {
+                downloads.push(download);
+            }
+            if let Some(depot) = DepotDownload::from_line(&l) {
+                depots.push(depot);
+            }
+            let progress = tracker.observe(&l, Instant::now());
+            let prompted = steam_guard::is_prompt(&l);
+            sender.send(InstallEvent::Output { stream, line: l });
+            if let Some(progress) = progress {
+                sender.send(InstallEvent::Progress(progress));
+            }
+            if prompted {
+                match guard.generated_code() {
+                    Some(code) => {
+                        debug!("answering Steam Guard prompt with a generated code");
+                        write_code(&mut stdin, &code).await?;
+                    }
+                    None => {
+                        debug!("waiting for an operator to supply a Steam Guard code");
+                        awaiting_code = true;
+                        guard.set_waiting(true);
+                        sender.send(InstallEvent::AwaitingGuardCode);
+                    }
+                }
+            }
+        }
+
+        // Output can close before steamcmd exits, so the overall limit still
+        // applies while waiting on it.
+        let waited = match self.timeouts.overall {
+            Some(limit) => tokio::time::timeout_at(started + limit, proc.wait()).await,
+            None => Ok(proc.wait().await),
+        };
+        let status = match waited {
+            Ok(status) => status?,
+            Err(_) => {
+                kill_tree(&mut proc).await;
+                return Err(self.timeouts.expired(InstallError::InstallTimeout).into());
+            }
+        };
+        debug!("steamcmd {} exited with {}", kind, status);
+        let exit_code = status.code();
+        match failure {
+            Some((error, message)) => Err(InstallFailure {
+                error,
+                message,
+                exit_code,
+            }
+            .into()),
+            None if !status.success() => Err(InstallFailure {
+                error: InstallError::Unknown,
+                message: format!("steamcmd exited with {}", status),
+                exit_code,
+            }
+            .into()),
+            None => Ok(Finished {
+                exit_code: exit_code.unwrap_or_default(),
+                // steamcmd prints no install state for an app it has never
+                // installed.
+                files_intact: match kind {
+                    JobKind::Verify => Some(intact.unwrap_or(false)),
+                    _ => None,
+                },
+                depots,
+            }),
+        }
+    }
+}
+
+/// Splits process output into lines, also treating a Steam Guard prompt as a
+/// line since steamcmd waits on it without printing a newline.
+struct OutputLines<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> OutputLines<R> {
+    fn new(reader: R) -> Self {
+        OutputLines {
+            reader,
+            buf: Vec::new(),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> String {
+        let line: Vec<u8> = self.buf.drain(..len).collect();
+        String::from_utf8_lossy(&line)
+            .trim_end_matches(['\r', '\n'])
+            .to_string()
+    }
+
+    async fn next_line(&mut self) -> std::io::Result<Option<String>> {
+        let mut chunk = [0; 4096];
+        loop {
+            if let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+                return Ok(Some(self.take(end + 1)));
+            }
+            if steam_guard::is_prompt(&String::from_utf8_lossy(&self.buf)) {
+                return Ok(Some(self.take(self.buf.len())));
+            }
+            let read = self.reader.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(match self.buf.is_empty() {
+                    true => None,
+                    false => Some(self.take(self.buf.len())),
+                });
+            }
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+async fn write_code<W: AsyncWriteExt + Unpin>(stdin: &mut W, code: &str) -> std::io::Result<()> {
+    stdin.write_all(format!("{}\n", code).as_bytes()).await?;
+    stdin.flush().await
+}
+
+/// Kills steamcmd along with anything it started.
+async fn kill_tree(proc: &mut Child) {
+    if let Some(pid) = proc.id() {
+        unsafe {
+            libc::kill(-(pid as i32), libc::SIGKILL);
+        }
+    }
+    if let Err(e) = proc.kill().await {
+        debug!("steamcmd had already exited: {}", e);
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
     use super::*;
+    use crate::events::EventHub;
+    use crate::retry::RetryPolicy;
+
+    fn no_guard() -> SteamGuard {
+        SteamGuard::new(None, tokio::sync::mpsc::channel(1).1).0
+    }
+
+    fn fake_steamcmd(dir: &Path, script: &str) -> anyhow::Result<String> {
+        let path = dir.join("steamcmd.sh");
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        Ok(path.display().to_string())
+    }
 
     #[tokio::test]
     async fn test_run() -> anyhow::Result<()> {
         let client: Client = Client::new("echo");
-        let commands = vec![SteamCommand {
-            command: "hello",
-            args: &["world"],
-        }];
-        let output = client.run(&commands)?.wait_with_output().await?;
+        let output = client
+            .run(Path::new("/tmp/steamcmd script.txt"))?
+            .wait_with_output()
+            .await?;
         let output_str = String::from_utf8(output.stdout)?;
 
-        assert_eq!(String::from("hello world"), output_str.trim_end());
+        assert_eq!(
+            String::from("+runscript /tmp/steamcmd script.txt"),
+            output_str.trim_end()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_app_update() {
+        let mut server = Server::new(107410, "arma3", "operator", "arma3");
+        let app_update =
+            |beta: Option<&str>, beta_password: Option<&str>, validate| SteamCommand::AppUpdate {
+                app_id: 107410,
+                beta: beta.map(String::from),
+                beta_password: beta_password.map(String::from),
+                validate,
+            };
+        assert_eq!(app_update(None, None, false), server.app_update(false));
+        assert_eq!(app_update(None, None, true), server.app_update(true));
+
+        server.branch = Some("public".into());
+        server.branch_password = Some("CautionSlowProfilingAhead".into());
+        assert_eq!(app_update(None, None, true), server.app_update(true));
+
+        server.branch = Some("profiling".into());
+        assert_eq!(
+            app_update(Some("profiling"), Some("CautionSlowProfilingAhead"), true),
+            server.app_update(true)
+        );
+    }
+
+    #[test]
+    fn test_runscript() -> anyhow::Result<()> {
+        let server = Server::new(740, "csgo", "anonymous", "Counter Strike");
+        let script = runscript("/srv/steam", &server, JobKind::Verify, None, &[], &[]).render()?;
+
+        assert_eq!(
+            "force_install_dir \"/srv/steam/Counter Strike\"\n\
+             login anonymous\n\
+             app_status 740\n\
+             quit\n",
+            script
+        );
+
+        let mut server = Server::new(233780, "arma3", "anonymous", "arma3");
+        server.workshop_app_id = Some(107410);
+        let script = runscript(
+            "/srv/steam",
+            &server,
+            JobKind::Validate,
+            None,
+            &[450814997],
+            &[],
+        )
+        .render()?;
+        assert!(script.ends_with(
+            "app_update 233780 validate\n\
+             workshop_download_item 107410 450814997 validate\n\
+             quit\n"
+        ));
+        // Checking files never downloads anything.
+        let script = runscript(
+            "/srv/steam",
+            &server,
+            JobKind::Verify,
+            None,
+            &[450814997],
+            &[],
+        )
+        .render()?;
+        assert!(!script.contains("workshop_download_item"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_runscript_pinned() -> anyhow::Result<()> {
+        let mut server = Server::new(740, "csgo", "anonymous", "csgo");
+        let pins = [DepotPin::new(740, 741, "3543226452394537624")?];
+        let script =
+            runscript("/srv/steam", &server, JobKind::Update, None, &[], &pins).render()?;
+        assert!(script.ends_with(
+            "app_update 740\n\
+             download_depot 740 741 3543226452394537624\n\
+             quit\n"
+        ));
+
+        server.installed_platform = Some(Platform::Linux);
+        let script =
+            runscript("/srv/steam", &server, JobKind::Update, None, &[], &pins).render()?;
+        assert!(script.ends_with(
+            "download_depot 740 741 3543226452394537624\n\
+             quit\n"
+        ));
+        assert!(!script.contains("app_update"));
+
+        let script =
+            runscript("/srv/steam", &server, JobKind::Verify, None, &[], &pins).render()?;
+        assert!(script.contains("app_status 740"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_files_intact() {
+        assert_eq!(
+            Some(true),
+            files_intact(" - install state: Fully Installed,")
+        );
+        assert_eq!(
+            Some(false),
+            files_intact(" - install state: Fully Installed,Files Missing,")
+        );
+        assert_eq!(Some(false), files_intact(" - install state: Uninstalled,"));
+        assert_eq!(None, files_intact(" - size on disk: 1234 bytes"));
+    }
+
+    #[test]
+    fn test_platform() -> anyhow::Result<()> {
+        let server: Server = serde_json::from_str(
+            r#"{"id": 376030, "name": "ark", "login": "anonymous", "install_dir": "ark",
+                "platform": "windows", "installed_platform": "macos"}"#,
+        )?;
+        assert_eq!(Platform::Windows, server.target_platform());
+        // Only an install decides what is installed.
+        assert_eq!(None, server.installed_platform);
+
+        let server = Server::new(740, "csgo", "anonymous", "csgo");
+        assert_eq!(Platform::host(), server.target_platform());
+        Ok(())
+    }
+
+    #[test]
+    fn test_branch_password_not_serialized() -> anyhow::Result<()> {
+        let server: Server = serde_json::from_str(
+            r#"{"id": 107410, "name": "arma3", "login": "operator", "install_dir": "arma3",
+                "branch": "profiling", "branch_password": "CautionSlowProfilingAhead"}"#,
+        )?;
+        assert_eq!(Some("profiling"), server.beta());
+
+        let json = serde_json::to_value(&server)?;
+        assert_eq!("profiling", json["branch"]);
+        assert!(json.get("branch_password").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_locks() {
+        let locks = ServerLocks::new();
+        let first = locks.try_lock(1);
+        assert!(first.is_some());
+        // A different server is not held up.
+        assert!(locks.try_lock(2).is_some());
+        assert!(locks.try_lock(1).is_none());
+
+        drop(first);
+        assert!(locks.try_lock(1).is_some());
+    }
+
+    #[test]
+    fn test_error_from_line() {
+        assert_eq!(
+            Some(InstallError::NoSubscription),
+            InstallError::from_line("ERROR! Failed to install app '740' (No subscription)")
+        );
+        assert_eq!(
+            Some(InstallError::InvalidPlatform),
+            InstallError::from_line("ERROR! Failed to install app '2' (Invalid platform)")
+        );
+        assert_eq!(
+            Some(InstallError::DiskWriteFailure),
+            InstallError::from_line(
+                "Error! App '740' state is 0x202 after update job. Disk write failure"
+            )
+        );
+        assert_eq!(
+            Some(InstallError::MissingConfiguration),
+            InstallError::from_line("ERROR! Failed to install app '90' (Missing configuration)")
+        );
+        assert_eq!(
+            Some(InstallError::LoginRejected),
+            InstallError::from_line(
+                "Logging in user 'operator' to Steam Public...FAILED login with result code Invalid Password"
+            )
+        );
+        assert_eq!(
+            Some(InstallError::UpdateInterrupted),
+            InstallError::from_line("Error! App '740' state is 0x402 after update job.")
+        );
+        assert_eq!(
+            Some(InstallError::UpdateInterrupted),
+            InstallError::from_line(
+                " Update state (0x602) verifying update, progress: 12.00 (100 / 833)"
+            )
+        );
+        assert_eq!(
+            Some(InstallError::Timeout),
+            InstallError::from_line(
+                "Logging in user 'operator' to Steam Public...FAILED login with result code Timeout"
+            )
+        );
+        assert_eq!(
+            Some(InstallError::Unknown),
+            InstallError::from_line("ERROR! Failed to install app '740' (Something new)")
+        );
+        assert_eq!(
+            Some(InstallError::Timeout),
+            InstallError::from_line("ERROR! Failed to install app '740' (Timeout)")
+        );
+        assert_eq!(
+            None,
+            InstallError::from_line("Connecting anonymously to Steam Public...timed out, retrying")
+        );
+        assert!(InstallError::Timeout.is_transient());
+        assert!(!InstallError::NoSubscription.is_transient());
+        assert_eq!(
+            None,
+            InstallError::from_line("Success! App '740' fully installed.")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_install_recovered() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let steamcmd = fake_steamcmd(
+            dir.path(),
+            "echo \"ERROR! Failed to install app '740' (Timeout)\"\n\
+             echo \" Update state (0x602) verifying update, progress: 12.00 (100 / 833)\"\n\
+             echo \"Success! App '740' fully installed.\"\n\
+             exit 0",
+        )?;
+        let client = Client::new(&steamcmd);
+        let hub = EventHub::new();
+        let server = Server::new(740, "csgo", "anonymous", "csgo");
+
+        let finished = client
+            .install(
+                &runscript(
+                    &dir.path().display().to_string(),
+                    &server,
+                    JobKind::Update,
+                    None,
+                    &[],
+                    &[],
+                ),
+                JobKind::Update,
+                &mut no_guard(),
+                &hub.publisher(1, 740),
+                &Notify::new(),
+                &mut vec![],
+            )
+            .await?;
+
+        assert_eq!(0, finished.exit_code);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recovered_timeout_not_retried() -> anyhow::Result<()> {
+        let policy = RetryPolicy::new(3, Duration::from_secs(30));
+        for output in [
+            "echo \"Connecting anonymously to Steam Public...timed out, retrying\"",
+            "echo \"ERROR! Failed to install app '740' (Timeout)\"\n\
+             echo \"Success! App '740' fully installed.\"",
+        ] {
+            let dir = tempfile::tempdir()?;
+            let steamcmd = fake_steamcmd(dir.path(), &format!("{}\nexit 0", output))?;
+            let client = Client::new(&steamcmd);
+            let hub = EventHub::new();
+            let server = Server::new(740, "csgo", "anonymous", "csgo");
+
+            let result = client
+                .install(
+                    &runscript(
+                        &dir.path().display().to_string(),
+                        &server,
+                        JobKind::Update,
+                        None,
+                        &[],
+                        &[],
+                    ),
+                    JobKind::Update,
+                    &mut no_guard(),
+                    &hub.publisher(1, 740),
+                    &Notify::new(),
+                    &mut vec![],
+                )
+                .await;
+
+            assert_eq!(None, result.err().and_then(|e| policy.delay(2, &e)));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_failure() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let steamcmd = fake_steamcmd(
+            dir.path(),
+            "echo \"Update state (0x61) downloading, progress: 50.00 (1 / 2)\"\n\
+             echo \"ERROR! Failed to install app '740' (No subscription)\"\n\
+             echo oops >&2\n\
+             exit 8",
+        )?;
+        let client = Client::new(&steamcmd);
+        let hub = EventHub::new();
+        let mut events = hub.subscribe_job(1);
+        let server = Server::new(740, "csgo", "anonymous", "csgo");
+
+        let err = client
+            .install(
+                &runscript(
+                    &dir.path().display().to_string(),
+                    &server,
+                    JobKind::Update,
+                    None,
+                    &[],
+                    &[],
+                ),
+                JobKind::Update,
+                &mut no_guard(),
+                &hub.publisher(1, 740),
+                &Notify::new(),
+                &mut vec![],
+            )
+            .await
+            .unwrap_err();
+        let failure = err.downcast_ref::<InstallFailure>().unwrap();
+
+        assert_eq!(InstallError::NoSubscription, failure.error);
+        assert_eq!(Some(8), failure.exit_code);
+
+        let mut stderr = vec![];
+        let mut progress = 0;
+        while let Ok(event) = events.try_recv() {
+            match event {
+                InstallEvent::Output {
+                    stream: OutputStream::Stderr,
+                    line,
+                } => stderr.push(line),
+                InstallEvent::Progress(_) => progress += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(vec![String::from("oops")], stderr);
+        assert_eq!(1, progress);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_exit_status() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let base_dir = dir.path().display().to_string();
+        let server = Server::new(740, "csgo", "anonymous", "csgo");
+        let hub = EventHub::new();
+
+        let client = Client::new(&fake_steamcmd(dir.path(), "exit 0")?);
+        assert_eq!(
+            0,
+            client
+                .install(
+                    &runscript(&base_dir, &server, JobKind::Update, None, &[], &[]),
+                    JobKind::Update,
+                    &mut no_guard(),
+                    &hub.publisher(1, 740),
+                    &Notify::new(),
+                    &mut vec![]
+                )
+                .await?
+                .exit_code
+        );
+
+        let client = Client::new(&fake_steamcmd(dir.path(), "exit 3")?);
+        let err = client
+            .install(
+                &runscript(&base_dir, &server, JobKind::Update, None, &[], &[]),
+                JobKind::Update,
+                &mut no_guard(),
+                &hub.publisher(2, 740),
+                &Notify::new(),
+                &mut vec![],
+            )
+            .await
+            .unwrap_err();
+        let failure = err.downcast_ref::<InstallFailure>().unwrap();
+        assert_eq!(InstallError::Unknown, failure.error);
+        assert_eq!(Some(3), failure.exit_code);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_times_out() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let base_dir = dir.path().display().to_string();
+        let server = Server::new(740, "csgo", "anonymous", "csgo");
+        let hub = EventHub::new();
+        let script = runscript(&base_dir, &server, JobKind::Update, None, &[], &[]);
+
+        let quiet = Client::new(&fake_steamcmd(dir.path(), "echo started\nsleep 30")?)
+            .with_timeouts(Timeouts {
+                overall: None,
+                output: Some(Duration::from_millis(200)),
+            });
+        let started = Instant::now();
+        let err = quiet
+            .install(
+                &script,
+                JobKind::Update,
+                &mut no_guard(),
+                &hub.publisher(1, 740),
+                &Notify::new(),
+                &mut vec![],
+            )
+            .await
+            .unwrap_err();
+        let failure = err.downcast_ref::<InstallFailure>().unwrap();
+        assert_eq!(InstallError::OutputTimeout, failure.error);
+        assert_eq!(None, failure.exit_code);
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let chatty = Client::new(&fake_steamcmd(
+            dir.path(),
+            "while true; do echo downloading; sleep 0.05; done",
+        )?)
+        .with_timeouts(Timeouts {
+            overall: Some(Duration::from_millis(500)),
+            output: Some(Duration::from_millis(200)),
+        });
+        let err = chatty
+            .install(
+                &script,
+                JobKind::Update,
+                &mut no_guard(),
+                &hub.publisher(2, 740),
+                &Notify::new(),
+                &mut vec![],
+            )
+            .await
+            .unwrap_err();
+        let failure = err.downcast_ref::<InstallFailure>().unwrap();
+        assert_eq!(InstallError::InstallTimeout, failure.error);
+        assert!(!crate::retry::is_transient(&err));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_app_info() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let steamcmd = fake_steamcmd(
+            dir.path(),
+            "cat \"$2\"\n\
+             echo 'AppID : 740, change number : 20194432/0'\n\
+             printf '\"740\"\\n{\\n\"depots\" { \"branches\" { \"public\" { \"buildid\" \"42\" } } }\\n}\\n'",
+        )?;
+        let info = Client::new(&steamcmd).app_info(740).await?;
+        assert_eq!(Some(42), info.build_id(None));
+
+        let client = Client::new(&fake_steamcmd(
+            dir.path(),
+            "echo 'No app info for AppID 740'",
+        )?);
+        assert!(client.app_info(740).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeouts_from_secs() {
+        assert_eq!(
+            Timeouts {
+                overall: Some(Duration::from_secs(60)),
+                output: None,
+            },
+            Timeouts::from_secs(60, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_install_cancel_kills_process_tree() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let pid_file = dir.path().join("child.pid");
+        let steamcmd = fake_steamcmd(
+            dir.path(),
+            &format!(
+                "sleep 30 &\necho $! > {}\necho started\nwait",
+                pid_file.display()
+            ),
+        )?;
+        let client = Client::new(&steamcmd);
+        let server = Server::new(740, "csgo", "anonymous", "csgo");
+        let hub = EventHub::new();
+        let mut events = hub.subscribe_job(1);
+        let cancel = Notify::new();
+
+        let script = runscript(
+            &dir.path().display().to_string(),
+            &server,
+            JobKind::Update,
+            None,
+            &[],
+            &[],
+        );
+        let publisher = hub.publisher(1, 740);
+        let mut guard = no_guard();
+        let mut downloads = vec![];
+        let install = client.install(
+            &script,
+            JobKind::Update,
+            &mut guard,
+            &publisher,
+            &cancel,
+            &mut downloads,
+        );
+        let cancel_once_started = async {
+            events.recv().await.unwrap();
+            cancel.notify_one();
+        };
+        let (result, _) = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            tokio::join!(install, cancel_once_started)
+        })
+        .await?;
+
+        let err = result.unwrap_err();
+        let failure = err.downcast_ref::<InstallFailure>().unwrap();
+        assert_eq!(InstallError::Cancelled, failure.error);
+
+        // The backgrounded sleep is gone too, or at worst left as a zombie.
+        let pid = fs::read_to_string(pid_file)?;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        if let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid.trim())) {
+            assert!(stat.contains(") Z "), "child still running: {}", stat);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_arguments() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let steamcmd = fake_steamcmd(dir.path(), "cat \"$2\"")?;
+        let client = Client::new(&steamcmd);
+        let mut server = Server::new(233780, "arma3", "operator", "arma3");
+        server.platform = Some(Platform::Windows);
+        let credential = Credential::new("operator", "hunter2");
+        let hub = EventHub::new();
+        let mut events = hub.subscribe_job(1);
+
+        client
+            .install(
+                &runscript(
+                    &dir.path().display().to_string(),
+                    &server,
+                    JobKind::Update,
+                    Some(&credential),
+                    &[],
+                    &[],
+                ),
+                JobKind::Update,
+                &mut no_guard(),
+                &hub.publisher(1, 233780),
+                &Notify::new(),
+                &mut vec![],
+            )
+            .await?;
+
+        let mut lines = vec![];
+        while let Ok(InstallEvent::Output { line, .. }) = events.try_recv() {
+            lines.push(line);
+        }
+        assert_eq!("@sSteamCmdForcePlatformType windows", lines[0]);
+        assert!(lines.contains(&String::from("login operator hunter2")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_reports_files_intact() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let steamcmd = fake_steamcmd(
+            dir.path(),
+            "cat \"$2\"\n\
+             echo \" - install state: Fully Installed,Files Corrupt,\"",
+        )?;
+        let client = Client::new(&steamcmd);
+        let server = Server::new(740, "csgo", "anonymous", "csgo");
+        let hub = EventHub::new();
+        let mut events = hub.subscribe_job(1);
+
+        let finished = client
+            .install(
+                &runscript(
+                    &dir.path().display().to_string(),
+                    &server,
+                    JobKind::Verify,
+                    None,
+                    &[],
+                    &[],
+                ),
+                JobKind::Verify,
+                &mut no_guard(),
+                &hub.publisher(1, 740),
+                &Notify::new(),
+                &mut vec![],
+            )
+            .await?;
+        assert_eq!(Some(false), finished.files_intact);
+
+        let mut lines = vec![];
+        while let Ok(InstallEvent::Output { line, .. }) = events.try_recv() {
+            lines.push(line);
+        }
+        assert!(lines.contains(&String::from("app_status 740")));
+        assert!(!lines.iter().any(|l| l.starts_with("app_update")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_waits_for_guard_code() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let steamcmd = fake_steamcmd(
+            dir.path(),
+            "printf 'Steam Guard code:'\nread code\necho \"got $code\"",
+        )?;
+        let client = Client::new(&steamcmd);
+        let server = Server::new(233780, "arma3", "operator", "arma3");
+        let credential = Credential::new("operator", "hunter2");
+        let hub = EventHub::new();
+        let mut events = hub.subscribe_job(1);
+        let (codes, rx) = tokio::sync::mpsc::channel(1);
+        let (mut guard, mut waiting) = SteamGuard::new(None, rx);
+
+        let script = runscript(
+            &dir.path().display().to_string(),
+            &server,
+            JobKind::Update,
+            Some(&credential),
+            &[],
+            &[],
+        );
+        let publisher = hub.publisher(1, 233780);
+        let cancel = Notify::new();
+        let mut downloads = vec![];
+        let install = client.install(
+            &script,
+            JobKind::Update,
+            &mut guard,
+            &publisher,
+            &cancel,
+            &mut downloads,
+        );
+        let operator = async {
+            while let Ok(event) = events.recv().await {
+                if event == InstallEvent::AwaitingGuardCode {
+                    assert!(*waiting.borrow_and_update());
+                    codes.send(String::from("ABCDE")).await.unwrap();
+                }
+                if let InstallEvent::Output { line, .. } = event {
+                    if line.starts_with("got") {
+                        return line;
+                    }
+                }
+            }
+            String::new()
+        };
+        let (result, line) = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            tokio::join!(install, operator)
+        })
+        .await?;
+
+        result?;
+        assert_eq!("got ABCDE", line);
+        assert!(!*waiting.borrow());
         Ok(())
     }
 }
@@ -0,0 +1,353 @@
+use std::{fmt, str::FromStr};
+
+use crate::install::{Finished, InstallError, InstallFailure};
+use crate::schema::{job_attempts, jobs};
+use chrono::NaiveDateTime;
+use diesel::{sql_types::Text, Insertable, Queryable};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum JobStatus {
+    Queued,
+    Running,
+    AwaitingGuardCode,
+    Succeeded,
+    Failed,
+    Cancelled,
+    /// Was running when the manager stopped.
+    Interrupted,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::AwaitingGuardCode => "awaiting_guard_code",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+            JobStatus::Interrupted => "interrupted",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Succeeded
+                | JobStatus::Failed
+                | JobStatus::Cancelled
+                | JobStatus::Interrupted
+        )
+    }
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(JobStatus::Queued),
+            "running" => Ok(JobStatus::Running),
+            "awaiting_guard_code" => Ok(JobStatus::AwaitingGuardCode),
+            "succeeded" => Ok(JobStatus::Succeeded),
+            "failed" => Ok(JobStatus::Failed),
+            "cancelled" => Ok(JobStatus::Cancelled),
+            "interrupted" => Ok(JobStatus::Interrupted),
+            other => Err(anyhow::anyhow!("unknown job status: {}", other)),
+        }
+    }
+}
+
+text_column!(JobStatus);
+
+/// What a job asks steamcmd to do with a server's files.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum JobKind {
+    /// Downloads whatever changed since the last update.
+    Update,
+    /// Updates and then checks every file, repairing any that are damaged.
+    Validate,
+    /// Only reports whether the installed files are intact.
+    Verify,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Update => "update",
+            JobKind::Validate => "validate",
+            JobKind::Verify => "verify",
+        }
+    }
+
+    /// Whether a job of this kind does everything one of `other` would, so
+    /// queueing both is pointless.
+    pub fn covers(&self, other: JobKind) -> bool {
+        *self == other || (*self == JobKind::Validate && other == JobKind::Update)
+    }
+
+    /// Whether the job downloads files, rather than only looking at them.
+    pub fn writes_files(&self) -> bool {
+        !matches!(self, JobKind::Verify)
+    }
+}
+
+impl fmt::Display for JobKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for JobKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "update" => Ok(JobKind::Update),
+            "validate" => Ok(JobKind::Validate),
+            "verify" => Ok(JobKind::Verify),
+            other => Err(anyhow::anyhow!("unknown job kind: {}", other)),
+        }
+    }
+}
+
+text_column!(JobKind);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Queryable)]
+pub struct Job {
+    pub id: i32,
+    pub server_id: i32,
+    pub status: JobStatus,
+    pub created_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+    pub exit_code: Option<i32>,
+    pub error_message: Option<String>,
+    pub error: Option<InstallError>,
+    pub kind: JobKind,
+    /// Set by verify jobs once they have checked the files.
+    pub files_intact: Option<bool>,
+    pub priority: i32,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "jobs"]
+pub struct NewJob {
+    pub server_id: i32,
+    pub status: JobStatus,
+    pub created_at: NaiveDateTime,
+    pub kind: JobKind,
+}
+
+impl NewJob {
+    pub fn queued(server_id: i32, kind: JobKind) -> Self {
+        NewJob {
+            server_id,
+            status: JobStatus::Queued,
+            created_at: chrono::Utc::now().naive_utc(),
+            kind,
+        }
+    }
+}
+
+/// One steamcmd run of a job, which may take several when runs fail in ways
+/// worth retrying.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Queryable)]
+pub struct JobAttempt {
+    pub id: i32,
+    pub job_id: i32,
+    pub attempt: i32,
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub exit_code: Option<i32>,
+    pub error: Option<InstallError>,
+    pub error_message: Option<String>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "job_attempts"]
+pub struct NewJobAttempt {
+    pub job_id: i32,
+    pub attempt: i32,
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub exit_code: Option<i32>,
+    pub error: Option<InstallError>,
+    pub error_message: Option<String>,
+}
+
+impl NewJobAttempt {
+    pub fn new(job_id: i32, attempt: u32, started_at: NaiveDateTime, outcome: &JobOutcome) -> Self {
+        NewJobAttempt {
+            job_id,
+            attempt: attempt as i32,
+            started_at,
+            finished_at: outcome
+                .finished_at
+                .unwrap_or_else(|| chrono::Utc::now().naive_utc()),
+            exit_code: outcome.exit_code,
+            error: outcome.error,
+            error_message: outcome.error_message.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, AsChangeset)]
+#[table_name = "jobs"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct JobOutcome {
+    pub status: JobStatus,
+    pub finished_at: Option<NaiveDateTime>,
+    pub exit_code: Option<i32>,
+    pub error_message: Option<String>,
+    pub error: Option<InstallError>,
+    pub files_intact: Option<bool>,
+}
+
+impl JobOutcome {
+    pub fn succeeded(finished: &Finished) -> Self {
+        JobOutcome {
+            status: JobStatus::Succeeded,
+            finished_at: Some(chrono::Utc::now().naive_utc()),
+            exit_code: Some(finished.exit_code),
+            error_message: None,
+            error: None,
+            files_intact: finished.files_intact,
+        }
+    }
+
+    pub fn cancelled() -> Self {
+        JobOutcome {
+            status: JobStatus::Cancelled,
+            finished_at: Some(chrono::Utc::now().naive_utc()),
+            exit_code: None,
+            error_message: None,
+            error: None,
+            files_intact: None,
+        }
+    }
+
+    /// Records a job the manager stopped in the middle of, saying where it
+    /// went when it was queued again.
+    pub fn interrupted(requeued_as: Option<i32>) -> Self {
+        JobOutcome {
+            status: JobStatus::Interrupted,
+            finished_at: Some(chrono::Utc::now().naive_utc()),
+            exit_code: None,
+            error_message: Some(match requeued_as {
+                Some(job_id) => format!(
+                    "manager stopped during the job, queued again as job {}",
+                    job_id
+                ),
+                None => "manager stopped during the job".into(),
+            }),
+            error: None,
+            files_intact: None,
+        }
+    }
+
+    /// Records a failed install, keeping the steamcmd error and exit code when
+    /// the process got far enough to report them.
+    pub fn failed(err: &anyhow::Error) -> Self {
+        let failure = err.downcast_ref::<InstallFailure>();
+        if let Some(InstallError::Cancelled) = failure.map(|f| f.error) {
+            return JobOutcome::cancelled();
+        }
+        JobOutcome {
+            status: JobStatus::Failed,
+            finished_at: Some(chrono::Utc::now().naive_utc()),
+            exit_code: failure.and_then(|f| f.exit_code),
+            error_message: Some(err.to_string()),
+            error: Some(failure.map_or(InstallError::Unknown, |f| f.error)),
+            files_intact: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_round_trip() -> anyhow::Result<()> {
+        for status in [
+            JobStatus::Queued,
+            JobStatus::Running,
+            JobStatus::AwaitingGuardCode,
+            JobStatus::Succeeded,
+            JobStatus::Failed,
+            JobStatus::Cancelled,
+            JobStatus::Interrupted,
+        ] {
+            assert_eq!(status, status.as_str().parse()?);
+            assert_eq!(format!("\"{}\"", status), serde_json::to_string(&status)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_kind_round_trip() -> anyhow::Result<()> {
+        for kind in [JobKind::Update, JobKind::Validate, JobKind::Verify] {
+            assert_eq!(kind, kind.as_str().parse()?);
+            assert_eq!(format!("\"{}\"", kind), serde_json::to_string(&kind)?);
+        }
+        assert!(!JobKind::Verify.writes_files());
+        assert!(JobKind::Validate.covers(JobKind::Update));
+        assert!(!JobKind::Update.covers(JobKind::Validate));
+        assert!(!JobKind::Validate.covers(JobKind::Verify));
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupted_outcome() {
+        let outcome = JobOutcome::interrupted(Some(12));
+
+        assert_eq!(JobStatus::Interrupted, outcome.status);
+        assert!(outcome.status.is_finished());
+        assert_eq!(
+            Some("manager stopped during the job, queued again as job 12"),
+            outcome.error_message.as_deref()
+        );
+    }
+
+    #[test]
+    fn test_unknown_status() {
+        assert!("exploded".parse::<JobStatus>().is_err());
+    }
+
+    #[test]
+    fn test_failed_outcome_keeps_install_error() {
+        let err = anyhow::Error::new(InstallFailure {
+            error: InstallError::NoSubscription,
+            message: "ERROR! Failed to install app '740' (No subscription)".into(),
+            exit_code: Some(8),
+        });
+        let outcome = JobOutcome::failed(&err);
+
+        assert_eq!(JobStatus::Failed, outcome.status);
+        assert_eq!(Some(InstallError::NoSubscription), outcome.error);
+        assert_eq!(Some(8), outcome.exit_code);
+
+        let outcome = JobOutcome::failed(&anyhow::anyhow!("No such file or directory"));
+        assert_eq!(Some(InstallError::Unknown), outcome.error);
+        assert_eq!(None, outcome.exit_code);
+
+        let err = anyhow::Error::new(InstallFailure {
+            error: InstallError::Cancelled,
+            message: "install was cancelled".into(),
+            exit_code: None,
+        });
+        assert_eq!(JobStatus::Cancelled, JobOutcome::failed(&err).status);
+    }
+}
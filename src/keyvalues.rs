@@ -0,0 +1,247 @@
+use std::{iter::Peekable, str::Chars};
+
+use anyhow::Result;
+
+/// A value in a KeyValues document, either a string or a nested section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Section(KeyValues),
+}
+
+/// Valve's KeyValues format, as used by `.acf` app manifests and `.vdf`
+/// files, e.g.
+///
+/// ```text
+/// "AppState"
+/// {
+///     "appid"     "740"
+///     "UserConfig"
+///     {
+///         "betakey"   "public"
+///     }
+/// }
+/// ```
+///
+/// Keys are kept in document order and looked up ignoring case, as Steam does.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyValues(Vec<(String, Value)>);
+
+impl KeyValues {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut tokens = Tokens::new(input);
+        let document = section(&mut tokens, false)?;
+        Ok(document)
+    }
+
+    /// The first value for `key`.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    pub fn string(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            Value::String(s) => Some(s),
+            Value::Section(_) => None,
+        }
+    }
+
+    pub fn section(&self, key: &str) -> Option<&KeyValues> {
+        match self.get(key)? {
+            Value::Section(section) => Some(section),
+            Value::String(_) => None,
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value))
+    }
+}
+
+fn section(tokens: &mut Tokens, nested: bool) -> Result<KeyValues> {
+    let mut entries = vec![];
+    loop {
+        let key = match tokens.next()? {
+            Some(Token::String(key)) => key,
+            Some(Token::Close) if nested => return Ok(KeyValues(entries)),
+            None if !nested => return Ok(KeyValues(entries)),
+            Some(token) => anyhow::bail!("expected a key on line {}, got {}", tokens.line, token),
+            None => anyhow::bail!("section is not closed at the end of the document"),
+        };
+        let value = match tokens.next()? {
+            Some(Token::String(value)) => Value::String(value),
+            Some(Token::Open) => Value::Section(section(tokens, true)?),
+            Some(token) => anyhow::bail!(
+                "expected a value for {} on line {}, got {}",
+                key,
+                tokens.line,
+                token
+            ),
+            None => anyhow::bail!("{} has no value at the end of the document", key),
+        };
+        entries.push((key, value));
+    }
+}
+
+#[derive(Debug)]
+enum Token {
+    String(String),
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::String(s) => write!(f, "\"{}\"", s),
+            Token::Open => write!(f, "{{"),
+            Token::Close => write!(f, "}}"),
+        }
+    }
+}
+
+struct Tokens<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(input: &'a str) -> Self {
+        Tokens {
+            chars: input.chars().peekable(),
+            line: 1,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn next(&mut self) -> Result<Option<Token>> {
+        loop {
+            let c = match self.bump() {
+                Some(c) => c,
+                None => return Ok(None),
+            };
+            match c {
+                c if c.is_whitespace() => {}
+                '/' if self.chars.peek() == Some(&'/') => self.skip_line(),
+                // Platform conditionals such as `[$WIN32]` only apply to the
+                // game itself, so they are dropped.
+                '[' => self.skip_until(']')?,
+                '{' => return Ok(Some(Token::Open)),
+                '}' => return Ok(Some(Token::Close)),
+                '"' => return self.quoted().map(|s| Some(Token::String(s))),
+                c => return Ok(Some(Token::String(self.unquoted(c)))),
+            }
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while !matches!(self.bump(), Some('\n') | None) {}
+    }
+
+    fn skip_until(&mut self, end: char) -> Result<()> {
+        let line = self.line;
+        loop {
+            match self.bump() {
+                Some(c) if c == end => return Ok(()),
+                Some(_) => {}
+                None => anyhow::bail!("'{}' on line {} is never closed", end, line),
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<String> {
+        let line = self.line;
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.bump() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(c @ ('\\' | '"')) => s.push(c),
+                    // Steam writes Windows paths with unescaped backslashes.
+                    Some(c) => {
+                        s.push('\\');
+                        s.push(c);
+                    }
+                    None => {}
+                },
+                Some(c) => s.push(c),
+                None => anyhow::bail!("string starting on line {} is never closed", line),
+            }
+        }
+    }
+
+    fn unquoted(&mut self, first: char) -> String {
+        let mut s = String::from(first);
+        while let Some(&c) = self.chars.peek() {
+            if c.is_whitespace() || matches!(c, '"' | '{' | '}') {
+                break;
+            }
+            s.push(c);
+            self.bump();
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let document = KeyValues::parse(
+            r#"// written by steamcmd
+"AppState"
+{
+	"appid"		"740"
+	"name"		"Counter-Strike \"GO\" Dedicated Server"
+	"installdir"	"C:\Games\csgo"
+	"UserConfig"
+	{
+		"betakey"		"public"
+	}
+	Empty {}
+	"flag"	"1"	[$WIN32]
+}
+"#,
+        )?;
+
+        let state = document.section("appstate").unwrap();
+        assert_eq!(Some("740"), state.string("AppID"));
+        assert_eq!(
+            Some("Counter-Strike \"GO\" Dedicated Server"),
+            state.string("name")
+        );
+        assert_eq!(Some("C:\\Games\\csgo"), state.string("installdir"));
+        assert_eq!(
+            Some("public"),
+            state.section("UserConfig").unwrap().string("betakey")
+        );
+        assert_eq!(Some(&KeyValues::default()), state.section("Empty"));
+        assert_eq!(Some("1"), state.string("flag"));
+        assert_eq!(None, state.string("UserConfig"));
+        assert_eq!(6, state.entries().count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(KeyValues::parse("\"AppState\" {").is_err());
+        assert!(KeyValues::parse("\"AppState\" }").is_err());
+        assert!(KeyValues::parse("\"AppState\"").is_err());
+        assert!(KeyValues::parse("\"AppState\" \"never closed").is_err());
+        assert!(KeyValues::parse("{ \"appid\" \"740\" }").is_err());
+    }
+}
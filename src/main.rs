@@ -1,33 +1,75 @@
 #[macro_use]
 extern crate diesel;
 
-use std::sync::Mutex;
-
 use config::Config;
 use handlers::{
     apps::{generate_apps, search_apps},
-    server::{create_server, delete, get_server, install_events, list_servers},
+    credentials::{delete_credential, list_credentials, save_credential},
+    jobs::{cancel_job, get_job, job_attempts, job_events, submit_guard_code},
+    pins::{list_depot_pins, pin_depot, unpin_depot},
+    queue::{change_queued_job, install_queue},
+    server::{
+        create_server, delete, get_server, install_events, installed_build, list_servers,
+        list_snapshots, rollback, server_jobs, set_update_schedule, validate, verify,
+    },
+    steamcmd::{install_steamcmd, steamcmd_status},
+    storage::storage_usage,
     test::test_events,
-    Rx, Tx,
+    workshop::{
+        add_workshop_item, import_workshop_collection, launch_args, list_workshop_items,
+        order_workshop_items, remove_workshop_item,
+    },
 };
-use install::{InstallQueueRx, InstallQueueTx, Server};
-use threadpool::ThreadPool;
 //use storage::FileStorage;
 //use serde::{Deserialize, Serialize};
 
+mod app_info;
+mod app_manifest;
+#[macro_use]
 mod db;
+mod credentials;
+mod depots;
+mod disk;
+mod events;
 mod handlers;
 mod install;
+mod jobs;
+mod keyvalues;
+mod progress;
+mod queue;
+mod retry;
+mod runscript;
+mod schedule;
 mod schema;
 mod service;
+mod snapshot;
 mod steam_apps;
+mod steam_guard;
+mod steamcmd;
 //mod storage;
 mod cors;
 mod types;
+mod workshop;
 
 #[macro_use]
 extern crate rocket;
 
+/// Rocket's configuration, with the database pool sized for the connections
+/// held for the life of the process: one per install worker, one each for
+/// the update checker and the scheduler, on top of Rocket's default for
+/// requests. A `pool_size` set in Rocket.toml is left as it is.
+fn figment(workers: usize) -> rocket::figment::Figment {
+    let figment = rocket::Config::figment();
+    if figment.find_value("databases.sqlite_db.pool_size").is_ok() {
+        return figment;
+    }
+    let requests = rocket::Config::from(&figment).workers * 4;
+    figment.merge(rocket::figment::providers::Serialized::default(
+        "databases.sqlite_db.pool_size",
+        workers + 2 + requests,
+    ))
+}
+
 #[rocket::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let settings: types::ServerConfig = Config::builder()
@@ -36,18 +78,85 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .set_default("steamcmd_location", "./steamcmd.sh")?
         .set_default("steam_api_url", "https://api.steampowered.com")?
         .set_default("workers", 4)?
+        .set_default("requeue_interrupted", false)?
+        .set_default("min_free_space_mb", 1024)?
+        .set_default("snapshot_mode", "none")?
+        .set_default("snapshot_retention", 3)?
+        .set_default("rollback_failed_updates", false)?
+        .set_default("install_retries", 3)?
+        .set_default("retry_backoff_seconds", 30)?
+        .set_default("install_timeout_seconds", 4 * 60 * 60)?
+        .set_default("output_timeout_seconds", 15 * 60)?
+        .set_default(
+            "steamcmd_url",
+            "https://steamcdn-a.akamaihd.net/client/installer/steamcmd_linux.tar.gz",
+        )?
+        .set_default("steamcmd_bootstrap", true)?
+        .set_default("update_check_minutes", 60)?
         .build()?
         .try_deserialize()?;
 
-    let thread_pool = ThreadPool::new(settings.workers);
-
     let app_service = service::SteamAppsService::new(&settings.steam_api_url);
     let storage = db::DBStorage {}; //FileStorage::new("./server_data");
-    let server_service = service::ServerService::new(storage);
-    let install_service =
-        service::InstallService::new(&settings.steamcmd_location, &settings.base_dir);
-    let (tx, rx) = flume::unbounded::<String>();
-    let (install_tx, install_rx) = flume::unbounded::<Server>();
+    let server_service = service::ServerService::new(storage.clone(), &settings.base_dir);
+    let job_service = service::JobService::new(storage.clone());
+    let credential_service = service::CredentialService::new(storage.clone());
+    let workshop_service = service::WorkshopService::new(storage.clone(), &settings.steam_api_url);
+    let steamcmd_service = service::SteamCmdService::new(
+        storage,
+        steamcmd::Bootstrap::new(
+            &settings.steamcmd_location,
+            &settings.steamcmd_url,
+            settings.steamcmd_sha256.as_deref(),
+        ),
+        settings.steamcmd_bootstrap,
+    );
+    let timeouts = install::Timeouts::from_secs(
+        settings.install_timeout_seconds,
+        settings.output_timeout_seconds,
+    );
+    let update_service = service::UpdateService::new(
+        &settings.steamcmd_location,
+        timeouts,
+        server_service.clone(),
+        match settings.update_check_minutes {
+            0 => None,
+            minutes => Some(std::time::Duration::from_secs(minutes * 60)),
+        },
+    );
+    let events = events::EventHub::new();
+    let install_service = service::InstallService::new(
+        &settings.steamcmd_location,
+        &settings.base_dir,
+        settings.workers,
+        retry::RetryPolicy::new(
+            settings.install_retries,
+            std::time::Duration::from_secs(settings.retry_backoff_seconds),
+        ),
+        timeouts,
+        settings.requeue_interrupted,
+        server_service.clone(),
+        job_service.clone(),
+        credential_service.clone(),
+        workshop_service.clone(),
+        events.clone(),
+    )
+    .with_min_free_space(settings.min_free_space_mb * 1024 * 1024)
+    .with_snapshots(snapshot::SnapshotPolicy::new(
+        settings.snapshot_mode,
+        &match &settings.snapshot_dir {
+            Some(dir) => std::path::PathBuf::from(dir),
+            None => std::path::Path::new(&settings.base_dir).join(".snapshots"),
+        },
+        settings.snapshot_retention,
+        settings.rollback_failed_updates,
+    ));
+    let storage_service = service::StorageService::new(&settings.base_dir);
+    let update_scheduler = service::UpdateScheduler::new(
+        server_service.clone(),
+        job_service.clone(),
+        install_service.clone(),
+    );
     fern::Dispatch::new()
         .format(|out, message, record| {
             out.finish(format_args!(
@@ -62,20 +171,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .chain(std::io::stdout())
         .apply()?;
 
-    let _r = rocket::build()
+    let _r = rocket::custom(figment(settings.workers))
         .manage(app_service)
         .manage(settings)
         .manage(server_service)
-        .manage(Rx(rx))
-        .manage(Tx(tx))
-        .manage(InstallQueueRx(install_rx))
-        .manage(InstallQueueTx(install_tx))
-        .manage(Mutex::new(thread_pool))
+        .manage(job_service)
+        .manage(credential_service)
+        .manage(workshop_service)
+        .manage(steamcmd_service.clone())
+        .manage(events)
+        .manage(install_service.clone())
+        .manage(storage_service)
         .attach(steam_apps::Db::fairing())
         .attach(db::Db::fairing())
         .attach(cors::CORS)
+        .attach(steamcmd_service)
         .attach(install_service)
+        .attach(update_service)
+        .attach(update_scheduler)
         .mount("/apps", routes![search_apps, generate_apps])
+        .mount("/install", routes![install_queue, change_queued_job])
+        .mount("/storage", routes![storage_usage])
         .mount(
             "/server",
             routes![
@@ -83,10 +199,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 get_server,
                 list_servers,
                 crate::handlers::server::install,
+                validate,
+                verify,
                 install_events,
                 delete,
+                server_jobs,
+                installed_build,
+                set_update_schedule,
+                list_depot_pins,
+                pin_depot,
+                unpin_depot,
+                list_snapshots,
+                rollback,
+                list_workshop_items,
+                add_workshop_item,
+                remove_workshop_item,
+                order_workshop_items,
+                import_workshop_collection,
+                launch_args,
             ],
         )
+        .mount(
+            "/jobs",
+            routes![
+                get_job,
+                job_attempts,
+                job_events,
+                cancel_job,
+                submit_guard_code
+            ],
+        )
+        .mount(
+            "/credentials",
+            routes![list_credentials, save_credential, delete_credential],
+        )
+        .mount("/steamcmd", routes![steamcmd_status, install_steamcmd])
         .mount("/test", routes![test_events])
         .launch()
         .await?;
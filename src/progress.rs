@@ -0,0 +1,169 @@
+use std::time::Instant;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Weight given to the newest throughput sample when smoothing.
+const SMOOTHING: f64 = 0.3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub state: u32,
+    pub phase: String,
+    pub percent: f64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub bytes_per_second: Option<f64>,
+    pub eta_seconds: Option<u64>,
+}
+
+struct Sample {
+    at: Instant,
+    phase: String,
+    bytes_done: u64,
+}
+
+/// Turns steamcmd `Update state` lines into [`Progress`] updates, keeping
+/// enough history to estimate throughput and time remaining.
+pub struct ProgressTracker {
+    pattern: Regex,
+    last: Option<Sample>,
+    rate: Option<f64>,
+}
+
+impl Default for ProgressTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        ProgressTracker {
+            pattern: Regex::new(
+                r"Update state \(0x([0-9a-fA-F]+)\) ([^,]+), progress: ([0-9.]+) \((\d+) / (\d+)\)",
+            )
+            .unwrap(),
+            last: None,
+            rate: None,
+        }
+    }
+
+    pub fn parse(&self, line: &str) -> Option<Progress> {
+        let caps = self.pattern.captures(line)?;
+        Some(Progress {
+            state: u32::from_str_radix(&caps[1], 16).ok()?,
+            phase: caps[2].trim().into(),
+            percent: caps[3].parse().ok()?,
+            bytes_done: caps[4].parse().ok()?,
+            bytes_total: caps[5].parse().ok()?,
+            bytes_per_second: None,
+            eta_seconds: None,
+        })
+    }
+
+    pub fn observe(&mut self, line: &str, at: Instant) -> Option<Progress> {
+        let mut progress = self.parse(line)?;
+
+        match &self.last {
+            // Each phase counts its own bytes, so only compare like with like.
+            Some(last)
+                if last.phase == progress.phase && progress.bytes_done >= last.bytes_done =>
+            {
+                let elapsed = at.duration_since(last.at).as_secs_f64();
+                if elapsed > 0.0 {
+                    let sample = (progress.bytes_done - last.bytes_done) as f64 / elapsed;
+                    self.rate = Some(match self.rate {
+                        Some(rate) => SMOOTHING * sample + (1.0 - SMOOTHING) * rate,
+                        None => sample,
+                    });
+                }
+            }
+            _ => self.rate = None,
+        }
+
+        progress.bytes_per_second = self.rate;
+        progress.eta_seconds = self.rate.filter(|rate| *rate > 0.0).map(|rate| {
+            (progress.bytes_total.saturating_sub(progress.bytes_done) as f64 / rate).ceil() as u64
+        });
+        self.last = Some(Sample {
+            at,
+            phase: progress.phase.clone(),
+            bytes_done: progress.bytes_done,
+        });
+
+        Some(progress)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let tracker = ProgressTracker::new();
+        let progress = tracker
+            .parse(" Update state (0x61) downloading, progress: 45.23 (1234 / 5678)")
+            .unwrap();
+
+        assert_eq!(0x61, progress.state);
+        assert_eq!("downloading", progress.phase);
+        assert_eq!(45.23, progress.percent);
+        assert_eq!(1234, progress.bytes_done);
+        assert_eq!(5678, progress.bytes_total);
+        assert_eq!(None, progress.bytes_per_second);
+    }
+
+    #[test]
+    fn test_parse_other_lines() {
+        let tracker = ProgressTracker::new();
+        assert_eq!(None, tracker.parse("Loading Steam API...OK"));
+        assert_eq!(None, tracker.parse("Success! App '740' fully installed."));
+    }
+
+    #[test]
+    fn test_throughput_and_eta() {
+        let mut tracker = ProgressTracker::new();
+        let start = Instant::now();
+
+        let first = tracker
+            .observe(
+                "Update state (0x61) downloading, progress: 10.00 (1000 / 10000)",
+                start,
+            )
+            .unwrap();
+        assert_eq!(None, first.eta_seconds);
+
+        let second = tracker
+            .observe(
+                "Update state (0x61) downloading, progress: 30.00 (3000 / 10000)",
+                start + Duration::from_secs(2),
+            )
+            .unwrap();
+        assert_eq!(Some(1000.0), second.bytes_per_second);
+        assert_eq!(Some(7), second.eta_seconds);
+    }
+
+    #[test]
+    fn test_phase_change_resets_rate() {
+        let mut tracker = ProgressTracker::new();
+        let start = Instant::now();
+
+        tracker.observe(
+            "Update state (0x61) downloading, progress: 90.00 (9000 / 10000)",
+            start,
+        );
+        let verifying = tracker
+            .observe(
+                "Update state (0x81) verifying update, progress: 5.00 (500 / 10000)",
+                start + Duration::from_secs(1),
+            )
+            .unwrap();
+
+        assert_eq!("verifying update", verifying.phase);
+        assert_eq!(None, verifying.bytes_per_second);
+    }
+}
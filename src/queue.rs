@@ -0,0 +1,354 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{mpsc, Notify, OwnedMutexGuard};
+
+use crate::install::{InstallRequest, ServerLocks};
+use crate::jobs::JobKind;
+
+/// Install requests waiting for a worker. Unlike a channel, jobs can be taken
+/// back out of the queue before a worker picks them up.
+#[derive(Clone, Default)]
+pub struct InstallQueue {
+    requests: Arc<Mutex<Requests>>,
+    notify: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct Requests {
+    pending: VecDeque<InstallRequest>,
+    /// Handed to a worker but not started yet, so still open to being
+    /// deduplicated against.
+    claimed: Vec<InstallRequest>,
+}
+
+impl InstallQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a request behind every other of the same or higher priority.
+    pub fn push(&self, request: InstallRequest) {
+        insert(&mut self.requests.lock().unwrap().pending, request);
+        self.notify.notify_one();
+    }
+
+    /// The requests that have not started yet: those a worker is about to
+    /// start, then those still waiting.
+    pub fn waiting(&self) -> Vec<InstallRequest> {
+        let requests = self.requests.lock().unwrap();
+        requests
+            .claimed
+            .iter()
+            .chain(requests.pending.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Moves a request to where its new priority puts it, returning false if
+    /// it is no longer queued.
+    pub fn set_priority(&self, job_id: i32, priority: i32) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        let pending = &mut requests.pending;
+        match pending.iter().position(|r| r.job_id == job_id) {
+            Some(index) => {
+                let mut request = pending.remove(index).unwrap();
+                request.priority = priority;
+                insert(pending, request);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Puts a request first, raising its priority above whatever was first
+    /// so that it stays ahead of requests queued later, and of the others
+    /// when the queue is loaded again after a restart. Returns the new
+    /// priority.
+    pub fn move_to_front(&self, job_id: i32) -> Option<i32> {
+        let mut requests = self.requests.lock().unwrap();
+        let pending = &mut requests.pending;
+        let index = pending.iter().position(|r| r.job_id == job_id)?;
+        let mut request = pending.remove(index).unwrap();
+        if let Some(first) = pending.front() {
+            request.priority = request.priority.max(first.priority + 1);
+        }
+        let priority = request.priority;
+        pending.push_front(request);
+        Some(priority)
+    }
+
+    /// Changes what a queued request does, returning false if it is no longer
+    /// queued.
+    pub fn set_kind(&self, job_id: i32, kind: JobKind) -> bool {
+        let mut requests = self.requests.lock().unwrap();
+        let pending = &mut requests.pending;
+        match pending.iter_mut().find(|r| r.job_id == job_id) {
+            Some(request) => {
+                request.kind = kind;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Waits for the next request whose server no other job is working on,
+    /// returning it with that server's lock held. Requests for busy servers
+    /// stay queued rather than tying up a worker.
+    pub async fn pop(&self, locks: &ServerLocks) -> (InstallRequest, OwnedMutexGuard<()>) {
+        loop {
+            let notified = self.notify.notified();
+            {
+                let requests = &mut *self.requests.lock().unwrap();
+                let free = requests
+                    .pending
+                    .iter()
+                    .enumerate()
+                    .find_map(|(index, request)| {
+                        locks
+                            .try_lock(request.server.id)
+                            .map(|server_guard| (index, server_guard))
+                    });
+                if let Some((index, server_guard)) = free {
+                    let request = requests.pending.remove(index).unwrap();
+                    requests.claimed.push(request.clone());
+                    return (request, server_guard);
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Notes that a worker has started a request it popped.
+    pub fn started(&self, job_id: i32) {
+        self.requests
+            .lock()
+            .unwrap()
+            .claimed
+            .retain(|r| r.job_id != job_id);
+    }
+
+    /// Lets go of a popped request once its job is over, whether or not it
+    /// ever started, and has waiting workers look again at its server.
+    pub fn release(&self, job_id: i32) {
+        self.started(job_id);
+        self.wake();
+    }
+
+    /// Has waiting workers look again, once a server's lock is let go.
+    pub fn wake(&self) {
+        self.notify.notify_waiters();
+    }
+
+    pub fn remove(&self, job_id: i32) -> Option<InstallRequest> {
+        let mut requests = self.requests.lock().unwrap();
+        let pending = &mut requests.pending;
+        let index = pending.iter().position(|r| r.job_id == job_id)?;
+        pending.remove(index)
+    }
+}
+
+fn insert(pending: &mut VecDeque<InstallRequest>, request: InstallRequest) {
+    match pending.iter().position(|r| r.priority < request.priority) {
+        Some(index) => pending.insert(index, request),
+        None => pending.push_back(request),
+    }
+}
+
+struct JobHandle {
+    cancel: Arc<Notify>,
+    guard_codes: mpsc::Sender<String>,
+}
+
+/// Handles for talking to the jobs that workers have picked up.
+#[derive(Clone, Default)]
+pub struct RunningJobs {
+    jobs: Arc<Mutex<HashMap<i32, JobHandle>>>,
+}
+
+impl RunningJobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the job's cancellation signal and the receiving end for any
+    /// Steam Guard codes an operator submits.
+    pub fn register(&self, job_id: i32) -> (Arc<Notify>, mpsc::Receiver<String>) {
+        let cancel = Arc::new(Notify::new());
+        let (guard_codes, codes) = mpsc::channel(1);
+        self.jobs.lock().unwrap().insert(
+            job_id,
+            JobHandle {
+                cancel: cancel.clone(),
+                guard_codes,
+            },
+        );
+        (cancel, codes)
+    }
+
+    pub fn remove(&self, job_id: i32) {
+        self.jobs.lock().unwrap().remove(&job_id);
+    }
+
+    /// Asks a running job to stop, returning false if no worker has it.
+    pub fn cancel(&self, job_id: i32) -> bool {
+        match self.jobs.lock().unwrap().get(&job_id) {
+            Some(handle) => {
+                handle.cancel.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Passes a Steam Guard code on to a running job, returning false if no
+    /// worker has it or it already has a code waiting.
+    pub fn submit_guard_code(&self, job_id: i32, code: &str) -> bool {
+        match self.jobs.lock().unwrap().get(&job_id) {
+            Some(handle) => handle.guard_codes.try_send(code.into()).is_ok(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::install::Server;
+
+    fn request(job_id: i32) -> InstallRequest {
+        InstallRequest {
+            job_id,
+            kind: JobKind::Update,
+            priority: 0,
+            server: Server::new(740, "csgo", "anonymous", "csgo"),
+        }
+    }
+
+    fn order(queue: &InstallQueue) -> Vec<i32> {
+        queue.waiting().iter().map(|r| r.job_id).collect()
+    }
+
+    #[test]
+    fn test_priority() {
+        let queue = InstallQueue::new();
+        queue.push(request(1));
+        queue.push(InstallRequest {
+            priority: 5,
+            ..request(2)
+        });
+        queue.push(request(3));
+        queue.push(InstallRequest {
+            priority: 5,
+            ..request(4)
+        });
+        assert_eq!(vec![2, 4, 1, 3], order(&queue));
+
+        assert!(queue.set_priority(3, 10));
+        assert_eq!(vec![3, 2, 4, 1], order(&queue));
+        assert!(!queue.set_priority(9, 10));
+
+        assert_eq!(Some(11), queue.move_to_front(1));
+        assert_eq!(vec![1, 3, 2, 4], order(&queue));
+        assert_eq!(None, queue.move_to_front(9));
+
+        assert!(queue.set_kind(4, JobKind::Validate));
+        assert_eq!(JobKind::Validate, queue.waiting()[3].kind);
+    }
+
+    #[tokio::test]
+    async fn test_queue_order_and_remove() {
+        let queue = InstallQueue::new();
+        queue.push(request(1));
+        queue.push(request(2));
+        queue.push(request(3));
+
+        let locks = ServerLocks::new();
+        assert_eq!(2, queue.remove(2).unwrap().job_id);
+        assert!(queue.remove(2).is_none());
+        assert_eq!(1, queue.pop(&locks).await.0.job_id);
+        assert_eq!(3, queue.pop(&locks).await.0.job_id);
+    }
+
+    #[tokio::test]
+    async fn test_pop_skips_busy_servers() {
+        let queue = InstallQueue::new();
+        let locks = ServerLocks::new();
+        queue.push(request(1));
+        queue.push(request(2));
+        let mut other = request(3);
+        other.server = Server::new(233780, "arma3", "anonymous", "arma3");
+        queue.push(other);
+
+        let (first, server_guard) = queue.pop(&locks).await;
+        assert_eq!(1, first.job_id);
+        // Job 2 waits for job 1 to finish with its server.
+        assert_eq!(3, queue.pop(&locks).await.0.job_id);
+        let waiting = tokio::spawn({
+            let (queue, locks) = (queue.clone(), locks.clone());
+            async move { queue.pop(&locks).await.0.job_id }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        drop(server_guard);
+        queue.release(1);
+        assert_eq!(2, waiting.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_claimed_until_started() {
+        let queue = InstallQueue::new();
+        let locks = ServerLocks::new();
+        queue.push(request(1));
+        queue.push(request(2));
+
+        let (first, _server_guard) = queue.pop(&locks).await;
+        assert_eq!(vec![1, 2], order(&queue));
+        // Taken requests can no longer be changed.
+        assert!(!queue.set_kind(first.job_id, JobKind::Validate));
+
+        queue.started(first.job_id);
+        assert_eq!(vec![2], order(&queue));
+    }
+
+    #[tokio::test]
+    async fn test_pop_waits_for_push() {
+        let queue = InstallQueue::new();
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.pop(&ServerLocks::new()).await.0.job_id }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        queue.push(request(7));
+
+        assert_eq!(7, waiting.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_running() {
+        let running = RunningJobs::new();
+        assert!(!running.cancel(1));
+
+        let (cancel, _) = running.register(1);
+        assert!(running.cancel(1));
+        cancel.notified().await;
+
+        running.remove(1);
+        assert!(!running.cancel(1));
+    }
+
+    #[tokio::test]
+    async fn test_submit_guard_code() {
+        let running = RunningJobs::new();
+        assert!(!running.submit_guard_code(1, "ABCDE"));
+
+        let (_, mut codes) = running.register(1);
+        assert!(running.submit_guard_code(1, "ABCDE"));
+        assert_eq!(Some(String::from("ABCDE")), codes.recv().await);
+    }
+}
@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use crate::install::InstallFailure;
+
+/// How often and how patiently failed installs are run again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Runs after the first, so `0` never retries.
+    pub retries: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, backoff: Duration) -> Self {
+        RetryPolicy {
+            retries,
+            backoff,
+            max_backoff: Duration::from_secs(600),
+        }
+    }
+
+    /// How long to wait before the given attempt, counting from `1` for the
+    /// first, when `error` is worth another try at all.
+    pub fn delay(&self, attempt: u32, error: &anyhow::Error) -> Option<Duration> {
+        if attempt < 2 || attempt > self.retries + 1 || !is_transient(error) {
+            return None;
+        }
+        let doubled = self
+            .backoff
+            .checked_mul(1 << (attempt - 2).min(16))
+            .unwrap_or(self.max_backoff);
+        Some(doubled.min(self.max_backoff))
+    }
+}
+
+/// Only failures steamcmd reported as transient are retried; anything the
+/// manager itself got wrong would fail the same way again.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<InstallFailure>(),
+        Some(failure) if failure.error.is_transient()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::install::InstallError;
+
+    fn failure(error: InstallError) -> anyhow::Error {
+        InstallFailure {
+            error,
+            message: String::new(),
+            exit_code: Some(8),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::new(3, Duration::from_secs(30));
+        let timeout = failure(InstallError::Timeout);
+
+        assert_eq!(Some(Duration::from_secs(30)), policy.delay(2, &timeout));
+        assert_eq!(Some(Duration::from_secs(60)), policy.delay(3, &timeout));
+        assert_eq!(Some(Duration::from_secs(120)), policy.delay(4, &timeout));
+        assert_eq!(None, policy.delay(5, &timeout));
+
+        assert_eq!(
+            None,
+            policy.delay(2, &failure(InstallError::NoSubscription))
+        );
+        assert_eq!(None, policy.delay(2, &anyhow::anyhow!("no steamcmd")));
+    }
+
+    #[test]
+    fn test_delay_is_capped() {
+        let policy = RetryPolicy::new(40, Duration::from_secs(30));
+        let timeout = failure(InstallError::UpdateInterrupted);
+
+        assert_eq!(Some(policy.max_backoff), policy.delay(41, &timeout));
+    }
+}
@@ -0,0 +1,254 @@
+use std::io::Write;
+
+use anyhow::Result;
+use tempfile::NamedTempFile;
+
+use crate::install::Platform;
+
+const REDACTED: &str = "********";
+
+/// A single steamcmd console command, as written to a runscript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SteamCommand {
+    /// Must come before login for steamcmd to pick the platform's depots.
+    ForcePlatformType(Platform),
+    ForceInstallDir(String),
+    Login {
+        username: String,
+        password: Option<String>,
+    },
+    AppUpdate {
+        app_id: i32,
+        beta: Option<String>,
+        beta_password: Option<String>,
+        validate: bool,
+    },
+    AppStatus(i32),
+    /// Fetches one manifest of a depot, whatever build is current.
+    DownloadDepot {
+        app_id: i32,
+        depot_id: i32,
+        manifest_id: String,
+    },
+    /// Refreshes steamcmd's cached app info before it is printed.
+    AppInfoUpdate,
+    AppInfoPrint(i32),
+    WorkshopDownloadItem {
+        app_id: i32,
+        item_id: i64,
+        validate: bool,
+    },
+    Quit,
+}
+
+impl SteamCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            SteamCommand::ForcePlatformType(_) => "@sSteamCmdForcePlatformType",
+            SteamCommand::ForceInstallDir(_) => "force_install_dir",
+            SteamCommand::Login { .. } => "login",
+            SteamCommand::AppUpdate { .. } => "app_update",
+            SteamCommand::AppStatus(_) => "app_status",
+            SteamCommand::DownloadDepot { .. } => "download_depot",
+            SteamCommand::AppInfoUpdate => "app_info_update",
+            SteamCommand::AppInfoPrint(_) => "app_info_print",
+            SteamCommand::WorkshopDownloadItem { .. } => "workshop_download_item",
+            SteamCommand::Quit => "quit",
+        }
+    }
+
+    /// The command's arguments, with secrets swapped out when `redact` is set.
+    fn args(&self, redact: bool) -> Vec<String> {
+        let secret = |s: &String| match redact {
+            true => REDACTED.to_string(),
+            false => s.clone(),
+        };
+        match self {
+            SteamCommand::ForcePlatformType(platform) => vec![platform.as_str().into()],
+            SteamCommand::ForceInstallDir(dir) => vec![dir.clone()],
+            SteamCommand::Login { username, password } => {
+                let mut args = vec![username.clone()];
+                args.extend(password.iter().map(secret));
+                args
+            }
+            SteamCommand::AppUpdate {
+                app_id,
+                beta,
+                beta_password,
+                validate,
+            } => {
+                let mut args = vec![app_id.to_string()];
+                if let Some(beta) = beta {
+                    args.extend(["-beta".to_string(), beta.clone()]);
+                    if let Some(password) = beta_password {
+                        args.extend(["-betapassword".to_string(), secret(password)]);
+                    }
+                }
+                if *validate {
+                    args.push("validate".into());
+                }
+                args
+            }
+            SteamCommand::AppStatus(app_id) | SteamCommand::AppInfoPrint(app_id) => {
+                vec![app_id.to_string()]
+            }
+            SteamCommand::DownloadDepot {
+                app_id,
+                depot_id,
+                manifest_id,
+            } => vec![
+                app_id.to_string(),
+                depot_id.to_string(),
+                manifest_id.clone(),
+            ],
+            SteamCommand::AppInfoUpdate => vec!["1".into()],
+            SteamCommand::WorkshopDownloadItem {
+                app_id,
+                item_id,
+                validate,
+            } => {
+                let mut args = vec![app_id.to_string(), item_id.to_string()];
+                if *validate {
+                    args.push("validate".into());
+                }
+                args
+            }
+            SteamCommand::Quit => vec![],
+        }
+    }
+
+    fn line(&self, redact: bool) -> Result<String> {
+        let mut line = self.name().to_string();
+        for arg in self.args(redact) {
+            line.push(' ');
+            line.push_str(&quote(&arg)?);
+        }
+        Ok(line)
+    }
+}
+
+/// Quotes an argument when steamcmd would otherwise split it up, e.g. an
+/// install path with spaces in it.
+///
+/// steamcmd has no escape sequences, so a double quote or line break can not
+/// be passed at all.
+fn quote(arg: &str) -> Result<String> {
+    if arg.contains(['"', '\r', '\n']) {
+        anyhow::bail!("steamcmd can not be passed an argument containing quotes or line breaks");
+    }
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./@".contains(c));
+    Ok(match plain {
+        true => arg.to_string(),
+        false => format!("\"{}\"", arg),
+    })
+}
+
+/// The commands for one steamcmd run, passed to it with `+runscript`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Runscript {
+    commands: Vec<SteamCommand>,
+}
+
+impl Runscript {
+    /// Ends the script with `quit`, so steamcmd never waits at its prompt.
+    pub fn new(commands: Vec<SteamCommand>) -> Self {
+        let mut commands = commands;
+        if commands.last() != Some(&SteamCommand::Quit) {
+            commands.push(SteamCommand::Quit);
+        }
+        Runscript { commands }
+    }
+
+    pub fn render(&self) -> Result<String> {
+        self.lines(false)
+    }
+
+    /// The script as it is run, minus any passwords, for logging.
+    pub fn redacted(&self) -> String {
+        self.lines(true)
+            .unwrap_or_else(|e| format!("<unusable script: {}>", e))
+    }
+
+    fn lines(&self, redact: bool) -> Result<String> {
+        let mut script = String::new();
+        for command in &self.commands {
+            script.push_str(&command.line(redact)?);
+            script.push('\n');
+        }
+        Ok(script)
+    }
+
+    /// Writes the script to a temporary file, which is removed once dropped.
+    pub fn write(&self) -> Result<NamedTempFile> {
+        let mut file = tempfile::Builder::new()
+            .prefix("steamcmd-")
+            .suffix(".txt")
+            .tempfile()?;
+        file.write_all(self.render()?.as_bytes())?;
+        file.flush()?;
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() -> anyhow::Result<()> {
+        let script = Runscript::new(vec![
+            SteamCommand::ForcePlatformType(Platform::Windows),
+            SteamCommand::ForceInstallDir("/srv/steam/Arma 3".into()),
+            SteamCommand::Login {
+                username: "operator".into(),
+                password: Some("hunter 2".into()),
+            },
+            SteamCommand::AppUpdate {
+                app_id: 233780,
+                beta: Some("profiling".into()),
+                beta_password: Some("CautionSlowProfilingAhead".into()),
+                validate: true,
+            },
+        ]);
+
+        assert_eq!(
+            "@sSteamCmdForcePlatformType windows\n\
+             force_install_dir \"/srv/steam/Arma 3\"\n\
+             login operator \"hunter 2\"\n\
+             app_update 233780 -beta profiling -betapassword CautionSlowProfilingAhead validate\n\
+             quit\n",
+            script.render()?
+        );
+        assert_eq!(
+            "login operator \"********\"",
+            script.redacted().lines().nth(2).unwrap()
+        );
+        assert!(!script.redacted().contains("CautionSlowProfilingAhead"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote() -> anyhow::Result<()> {
+        assert_eq!("anonymous", quote("anonymous")?);
+        assert_eq!("\"\"", quote("")?);
+        assert_eq!("\"pass;word\"", quote("pass;word")?);
+        assert!(quote("say \"hi\"").is_err());
+        assert!(quote("two\nlines").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write() -> anyhow::Result<()> {
+        let script = Runscript::new(vec![SteamCommand::AppStatus(740)]);
+        let file = script.write()?;
+
+        assert_eq!(
+            "app_status 740\nquit\n",
+            std::fs::read_to_string(file.path())?
+        );
+        Ok(())
+    }
+}
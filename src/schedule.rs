@@ -0,0 +1,311 @@
+use std::{fmt, str::FromStr};
+
+use crate::install::Server;
+use crate::schema::servers;
+use anyhow::Result;
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, Utc};
+use diesel::{sql_types::Text, AsChangeset};
+use serde::{Deserialize, Serialize};
+
+/// When a server is updated without anyone asking for it. Times are UTC.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, AsExpression, FromSqlRow,
+)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum UpdatePolicy {
+    #[default]
+    Never,
+    /// Every day at the `HH:MM` in `update_schedule`.
+    Daily,
+    /// Whenever the cron expression in `update_schedule` fires, e.g.
+    /// `30 4 * * Mon`.
+    Cron,
+    /// As soon as a newer build shows up on the server's branch.
+    WhenAvailable,
+}
+
+impl UpdatePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpdatePolicy::Never => "never",
+            UpdatePolicy::Daily => "daily",
+            UpdatePolicy::Cron => "cron",
+            UpdatePolicy::WhenAvailable => "when_available",
+        }
+    }
+}
+
+impl fmt::Display for UpdatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for UpdatePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(UpdatePolicy::Never),
+            "daily" => Ok(UpdatePolicy::Daily),
+            "cron" => Ok(UpdatePolicy::Cron),
+            "when_available" => Ok(UpdatePolicy::WhenAvailable),
+            other => Err(anyhow::anyhow!("unknown update policy: {}", other)),
+        }
+    }
+}
+
+text_column!(UpdatePolicy);
+
+/// A server's update policy, as set with `PUT /server/<id>/schedule`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, AsChangeset)]
+#[table_name = "servers"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct UpdateSchedule {
+    pub update_policy: UpdatePolicy,
+    #[serde(default)]
+    pub update_schedule: Option<String>,
+    #[serde(default)]
+    pub maintenance_window: Option<String>,
+    #[serde(skip)]
+    pub next_update_at: Option<NaiveDateTime>,
+}
+
+/// Part of the day, e.g. `02:00-06:00`, that scheduled updates may start in.
+/// It may run past midnight, as in `22:00-02:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl MaintenanceWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        match self.start <= self.end {
+            true => self.start <= time && time < self.end,
+            false => self.start <= time || time < self.end,
+        }
+    }
+
+    /// The first moment from `after` on that falls in the window.
+    pub fn next_open(&self, after: NaiveDateTime) -> NaiveDateTime {
+        if self.contains(after.time()) {
+            return after;
+        }
+        let opens = after.date().and_time(self.start);
+        match opens > after {
+            true => opens,
+            false => opens + Duration::days(1),
+        }
+    }
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("maintenance window is not HH:MM-HH:MM: {}", s))?;
+        let window = MaintenanceWindow {
+            start: time_of_day(start)?,
+            end: time_of_day(end)?,
+        };
+        if window.start == window.end {
+            anyhow::bail!("maintenance window {} never opens", s);
+        }
+        Ok(window)
+    }
+}
+
+fn time_of_day(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s.trim(), "%H:%M")
+        .map_err(|e| anyhow::anyhow!("{} is not a time of day: {}", s, e))
+}
+
+#[derive(Debug, Clone)]
+enum Trigger {
+    None,
+    Daily(NaiveTime),
+    Cron(Box<cron::Schedule>),
+}
+
+/// A server's update policy, checked and ready to work out its next run.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    policy: UpdatePolicy,
+    trigger: Trigger,
+    window: Option<MaintenanceWindow>,
+}
+
+impl Schedule {
+    pub fn new(policy: UpdatePolicy, schedule: Option<&str>, window: Option<&str>) -> Result<Self> {
+        let schedule = schedule.map(str::trim).filter(|s| !s.is_empty());
+        let trigger = match (policy, schedule) {
+            (UpdatePolicy::Daily, Some(at)) => Trigger::Daily(time_of_day(at)?),
+            (UpdatePolicy::Cron, Some(expression)) => Trigger::Cron(Box::new(cron(expression)?)),
+            (UpdatePolicy::Daily | UpdatePolicy::Cron, None) => {
+                anyhow::bail!("a {} update policy needs an update_schedule", policy)
+            }
+            _ => Trigger::None,
+        };
+        let window = match window.map(str::trim).filter(|w| !w.is_empty()) {
+            Some(window) => Some(window.parse()?),
+            None => None,
+        };
+        Ok(Schedule {
+            policy,
+            trigger,
+            window,
+        })
+    }
+
+    pub fn for_server(server: &Server) -> Result<Self> {
+        Schedule::new(
+            server.update_policy,
+            server.update_schedule.as_deref(),
+            server.maintenance_window.as_deref(),
+        )
+    }
+
+    pub fn policy(&self) -> UpdatePolicy {
+        self.policy
+    }
+
+    pub fn in_window(&self, time: NaiveDateTime) -> bool {
+        match self.window {
+            Some(window) => window.contains(time.time()),
+            None => true,
+        }
+    }
+
+    /// The first moment from `after` on that an update may start in.
+    pub fn window_open(&self, after: NaiveDateTime) -> NaiveDateTime {
+        self.window.map_or(after, |window| window.next_open(after))
+    }
+
+    /// When the next update is due, which for `when_available` is only once
+    /// there is something to update to. Runs that fall outside the
+    /// maintenance window wait for it to open.
+    pub fn next_run(&self, after: NaiveDateTime, update_available: bool) -> Option<NaiveDateTime> {
+        let fires = match &self.trigger {
+            Trigger::Daily(at) => {
+                let today = after.date().and_time(*at);
+                match today > after {
+                    true => today,
+                    false => today + Duration::days(1),
+                }
+            }
+            Trigger::Cron(schedule) => schedule
+                .after(&DateTime::<Utc>::from_utc(after, Utc))
+                .next()?
+                .naive_utc(),
+            Trigger::None if self.policy == UpdatePolicy::WhenAvailable && update_available => {
+                after
+            }
+            Trigger::None => return None,
+        };
+        Some(self.window_open(fires))
+    }
+}
+
+/// Takes the usual five fields, or the `cron` crate's own form with seconds.
+fn cron(expression: &str) -> Result<cron::Schedule> {
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression),
+        _ => expression.to_string(),
+    };
+    cron::Schedule::from_str(&expression)
+        .map_err(|e| anyhow::anyhow!("{} is not a cron expression: {}", expression, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_update_policy_round_trip() -> anyhow::Result<()> {
+        for policy in [
+            UpdatePolicy::Never,
+            UpdatePolicy::Daily,
+            UpdatePolicy::Cron,
+            UpdatePolicy::WhenAvailable,
+        ] {
+            assert_eq!(policy, policy.as_str().parse()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_daily() -> anyhow::Result<()> {
+        let schedule = Schedule::new(UpdatePolicy::Daily, Some("04:30"), None)?;
+        assert_eq!(
+            Some(at("2026-10-18 04:30")),
+            schedule.next_run(at("2026-10-18 01:00"), false)
+        );
+        assert_eq!(
+            Some(at("2026-10-19 04:30")),
+            schedule.next_run(at("2026-10-18 04:30"), false)
+        );
+        assert!(Schedule::new(UpdatePolicy::Daily, None, None).is_err());
+        assert!(Schedule::new(UpdatePolicy::Daily, Some("25:00"), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cron() -> anyhow::Result<()> {
+        // 2026-10-18 is a Sunday.
+        let schedule = Schedule::new(UpdatePolicy::Cron, Some("15 3 * * Mon"), None)?;
+        assert_eq!(
+            Some(at("2026-10-19 03:15")),
+            schedule.next_run(at("2026-10-18 12:00"), false)
+        );
+        assert!(Schedule::new(UpdatePolicy::Cron, Some("every monday"), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_when_available() -> anyhow::Result<()> {
+        let schedule = Schedule::new(UpdatePolicy::WhenAvailable, None, Some("02:00-06:00"))?;
+        assert_eq!(None, schedule.next_run(at("2026-10-18 12:00"), false));
+        assert_eq!(
+            Some(at("2026-10-19 02:00")),
+            schedule.next_run(at("2026-10-18 12:00"), true)
+        );
+        assert_eq!(
+            Some(at("2026-10-18 03:00")),
+            schedule.next_run(at("2026-10-18 03:00"), true)
+        );
+        assert_eq!(
+            None,
+            Schedule::new(UpdatePolicy::Never, None, None)?.next_run(at("2026-10-18 03:00"), true)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_maintenance_window() -> anyhow::Result<()> {
+        let overnight: MaintenanceWindow = "22:00-02:00".parse()?;
+        assert!(overnight.contains(NaiveTime::from_hms(23, 0, 0)));
+        assert!(overnight.contains(NaiveTime::from_hms(1, 59, 0)));
+        assert!(!overnight.contains(NaiveTime::from_hms(2, 0, 0)));
+        assert_eq!(
+            at("2026-10-18 22:00"),
+            overnight.next_open(at("2026-10-18 12:00"))
+        );
+
+        let schedule = Schedule::new(UpdatePolicy::Daily, Some("12:00"), Some("22:00-02:00"))?;
+        assert_eq!(
+            Some(at("2026-10-18 22:00")),
+            schedule.next_run(at("2026-10-18 08:00"), false)
+        );
+        assert!("02:00".parse::<MaintenanceWindow>().is_err());
+        assert!("02:00-02:00".parse::<MaintenanceWindow>().is_err());
+        Ok(())
+    }
+}
@@ -1,9 +1,94 @@
+table! {
+    credentials (login) {
+        login -> Text,
+        password -> Text,
+        shared_secret -> Nullable<Text>,
+    }
+}
+
+table! {
+    depot_pins (server_id, depot_id) {
+        server_id -> Integer,
+        depot_id -> Integer,
+        manifest_id -> Text,
+        pinned_at -> Timestamp,
+        installed_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    job_attempts (id) {
+        id -> Integer,
+        job_id -> Integer,
+        attempt -> Integer,
+        started_at -> Timestamp,
+        finished_at -> Timestamp,
+        exit_code -> Nullable<Integer>,
+        error -> Nullable<Text>,
+        error_message -> Nullable<Text>,
+    }
+}
+
+table! {
+    jobs (id) {
+        id -> Integer,
+        server_id -> Integer,
+        status -> Text,
+        created_at -> Timestamp,
+        started_at -> Nullable<Timestamp>,
+        finished_at -> Nullable<Timestamp>,
+        exit_code -> Nullable<Integer>,
+        error_message -> Nullable<Text>,
+        error -> Nullable<Text>,
+        kind -> Text,
+        files_intact -> Nullable<Bool>,
+        priority -> Integer,
+    }
+}
+
 table! {
     servers (id) {
         id -> Integer,
         name -> Text,
         login -> Text,
         install_dir -> Text,
+        branch -> Nullable<Text>,
+        branch_password -> Nullable<Text>,
+        platform -> Nullable<Text>,
+        installed_platform -> Nullable<Text>,
+        workshop_app_id -> Nullable<Integer>,
+        mods_dir -> Nullable<Text>,
+        mods_link -> Text,
+        installed_build_id -> Nullable<BigInt>,
+        latest_build_id -> Nullable<BigInt>,
+        update_available -> Bool,
+        update_checked_at -> Nullable<Timestamp>,
+        update_policy -> Text,
+        update_schedule -> Nullable<Text>,
+        maintenance_window -> Nullable<Text>,
+        next_update_at -> Nullable<Timestamp>,
+        latest_size -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    snapshots (id) {
+        id -> Integer,
+        server_id -> Integer,
+        job_id -> Integer,
+        mode -> Text,
+        path -> Text,
+        build_id -> Nullable<BigInt>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    steamcmd_installs (id) {
+        id -> Integer,
+        version -> Nullable<Text>,
+        sha256 -> Text,
+        installed_at -> Timestamp,
     }
 }
 
@@ -14,4 +99,32 @@ table! {
     }
 }
 
-allow_tables_to_appear_in_same_query!(servers, steam_apps,);
+table! {
+    workshop_items (server_id, item_id) {
+        server_id -> Integer,
+        item_id -> BigInt,
+        status -> Text,
+        error_message -> Nullable<Text>,
+        updated_at -> Nullable<Timestamp>,
+        position -> Integer,
+    }
+}
+
+joinable!(depot_pins -> servers (server_id));
+joinable!(job_attempts -> jobs (job_id));
+joinable!(jobs -> servers (server_id));
+joinable!(snapshots -> jobs (job_id));
+joinable!(snapshots -> servers (server_id));
+joinable!(workshop_items -> servers (server_id));
+
+allow_tables_to_appear_in_same_query!(
+    credentials,
+    depot_pins,
+    job_attempts,
+    jobs,
+    servers,
+    snapshots,
+    steamcmd_installs,
+    steam_apps,
+    workshop_items,
+);
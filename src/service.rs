@@ -1,45 +1,437 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
 use anyhow::Result;
 use log::debug;
 use rocket::fairing::{Fairing, Info, Kind};
+use serde::Serialize;
+use tokio::sync::{mpsc, Mutex as AsyncMutex, Notify};
 
 use crate::{
+    app_info::BuildStatus,
+    app_manifest::InstalledBuild,
+    credentials::{Credential, ANONYMOUS},
     db::{DBStorage, Db},
-    handlers::Tx,
-    install::{self, InstallQueueRx, Server},
+    depots::{self, DepotDownload, DepotPin},
+    disk::{self, DiskUsage},
+    events::{EventHub, InstallEvent},
+    install::{self, InstallError, InstallFailure, InstallRequest, Server, ServerLocks, Timeouts},
+    jobs::{Job, JobAttempt, JobKind, JobOutcome, JobStatus, NewJob, NewJobAttempt},
+    queue::{InstallQueue, RunningJobs},
+    retry::RetryPolicy,
+    schedule::{Schedule, UpdatePolicy, UpdateSchedule},
+    snapshot::{self, NewSnapshot, Snapshot, SnapshotPolicy},
     steam_apps::{self, App},
+    steam_guard::SteamGuard,
+    steamcmd::{Bootstrap, SteamCmdInstall},
+    workshop::{self, CollectionResponse, ItemDownload, ItemStatus, WorkshopItem},
 };
 
+#[derive(Clone)]
 pub struct ServerService {
     storage: DBStorage,
+    base_dir: PathBuf,
 }
 
 impl ServerService {
-    pub fn new(storage: DBStorage) -> Self {
-        ServerService { storage }
+    pub fn new(storage: DBStorage, base_dir: &str) -> Self {
+        ServerService {
+            storage,
+            base_dir: base_dir.into(),
+        }
     }
 
-    pub async fn new_server(&self, server: &Server, db: Db) -> Result<()> {
+    pub fn install_dir(&self, server: &Server) -> PathBuf {
+        self.base_dir.join(&server.install_dir)
+    }
+
+    pub async fn new_server(&self, server: &Server, db: &Db) -> Result<()> {
         //let server = Server::new(id, name, login, name);
-        self.storage.save(server, db).await?;
+        let mut server = server.clone();
+        server.next_update_at = Schedule::for_server(&server)?
+            .next_run(chrono::Utc::now().naive_utc(), server.update_available);
+        self.storage.save(&server, db).await?;
         Ok(())
     }
 
-    pub async fn get_server(&self, id: i32, db: Db) -> Result<Server> {
+    pub async fn get_server(&self, id: i32, db: &Db) -> Result<Server> {
         self.storage.load(id, db).await
     }
 
-    pub async fn list_servers(&self, db: Db) -> Result<Vec<Server>> {
+    pub async fn list_servers(&self, db: &Db) -> Result<Vec<Server>> {
         self.storage.list(db).await
     }
 
-    pub async fn delete(&self, id: i32, db: Db) -> Result<()> {
+    pub async fn delete(&self, id: i32, db: &Db) -> Result<()> {
         self.storage.delete(id, db).await
     }
+
+    /// Records what a finished install left on disk.
+    pub async fn installed(&self, server: &Server, db: &Db) -> Result<()> {
+        self.storage
+            .set_installed_platform(server.id, server.target_platform(), db)
+            .await?;
+        let installed = InstalledBuild::read(&self.install_dir(server), server.id).await?;
+        // Keeps the last update check, so an install that did not reach the
+        // latest build still shows an update.
+        let current = self.get_server(server.id, db).await?;
+        let status = BuildStatus::new(
+            installed.as_ref(),
+            current.latest_build_id.map(|build_id| build_id as u64),
+            current.latest_size.map(|size| size as u64),
+            current.update_checked_at,
+        );
+        // download_depot leaves the app manifest alone, so it still describes
+        // whatever app_update installed last.
+        let status = match self.pins(server.id, db).await?.is_empty() {
+            true => status,
+            false => {
+                self.storage
+                    .set_depot_pins_installed(server.id, chrono::Utc::now().naive_utc(), db)
+                    .await?;
+                status.pinned()
+            }
+        };
+        self.storage.set_build_status(server.id, status, db).await
+    }
+
+    pub async fn set_build_status(&self, id: i32, status: BuildStatus, db: &Db) -> Result<()> {
+        self.storage.set_build_status(id, status, db).await
+    }
+
+    pub async fn pins(&self, id: i32, db: &Db) -> Result<Vec<DepotPin>> {
+        self.storage.list_depot_pins(id, db).await
+    }
+
+    /// Holds a depot at a manifest, replacing any earlier pin of it.
+    pub async fn pin(
+        &self,
+        id: i32,
+        depot_id: i32,
+        manifest_id: &str,
+        db: &Db,
+    ) -> Result<Vec<DepotPin>> {
+        let server = self.get_server(id, db).await?;
+        let pin = DepotPin::new(server.id, depot_id, manifest_id)?;
+        self.storage.save_depot_pin(&pin, db).await?;
+        self.pins(id, db).await
+    }
+
+    pub async fn unpin(&self, id: i32, depot_id: i32, db: &Db) -> Result<Vec<DepotPin>> {
+        self.storage.delete_depot_pin(id, depot_id, db).await?;
+        self.pins(id, db).await
+    }
+
+    /// The snapshots taken of a server before its updates, newest first.
+    pub async fn snapshots(&self, id: i32, db: &Db) -> Result<Vec<Snapshot>> {
+        self.storage.list_snapshots(id, db).await
+    }
+
+    pub async fn save_snapshot(&self, snapshot: &NewSnapshot, db: &Db) -> Result<Snapshot> {
+        self.storage.save_snapshot(snapshot, db).await
+    }
+
+    pub async fn delete_snapshot(&self, snapshot_id: i32, db: &Db) -> Result<()> {
+        self.storage.delete_snapshot(snapshot_id, db).await
+    }
+
+    /// Changes a server's update policy, working out its next run afresh.
+    pub async fn set_update_schedule(
+        &self,
+        id: i32,
+        schedule: UpdateSchedule,
+        db: &Db,
+    ) -> Result<Server> {
+        let server = self.get_server(id, db).await?;
+        let next_update_at = Schedule::new(
+            schedule.update_policy,
+            schedule.update_schedule.as_deref(),
+            schedule.maintenance_window.as_deref(),
+        )?
+        .next_run(chrono::Utc::now().naive_utc(), server.update_available);
+        let schedule = UpdateSchedule {
+            next_update_at,
+            ..schedule
+        };
+        self.storage.set_update_schedule(id, schedule, db).await?;
+        self.get_server(id, db).await
+    }
+
+    pub async fn set_next_update(
+        &self,
+        id: i32,
+        next: Option<chrono::NaiveDateTime>,
+        db: &Db,
+    ) -> Result<()> {
+        self.storage.set_next_update(id, next, db).await
+    }
+
+    /// The build steamcmd last installed, if it installed the server at all.
+    pub async fn installed_build(&self, id: i32, db: &Db) -> Result<Option<InstalledBuild>> {
+        let server = self.get_server(id, db).await?;
+        InstalledBuild::read(&self.install_dir(&server), server.id).await
+    }
+}
+
+#[derive(Clone)]
+pub struct JobService {
+    storage: DBStorage,
+}
+
+impl JobService {
+    pub fn new(storage: DBStorage) -> Self {
+        JobService { storage }
+    }
+
+    pub async fn new_job(&self, server_id: i32, kind: JobKind, db: &Db) -> Result<Job> {
+        self.storage
+            .save_job(&NewJob::queued(server_id, kind), db)
+            .await
+    }
+
+    pub async fn get_job(&self, id: i32, db: &Db) -> Result<Job> {
+        self.storage.load_job(id, db).await
+    }
+
+    pub async fn find_job(&self, id: i32, db: &Db) -> Result<Option<Job>> {
+        self.storage.find_job(id, db).await
+    }
+
+    pub async fn list_jobs(&self, server_id: i32, db: &Db) -> Result<Vec<Job>> {
+        self.storage.list_jobs(server_id, db).await
+    }
+
+    pub async fn set_priority(&self, id: i32, priority: i32, db: &Db) -> Result<()> {
+        self.storage.set_job_priority(id, priority, db).await
+    }
+
+    pub async fn set_queued_kind(&self, id: i32, kind: JobKind, db: &Db) -> Result<bool> {
+        self.storage.set_queued_job_kind(id, kind, db).await
+    }
+
+    pub async fn list_unfinished(&self, db: &Db) -> Result<Vec<Job>> {
+        self.storage.list_unfinished_jobs(db).await
+    }
+
+    pub async fn start(&self, id: i32, db: &Db) -> Result<bool> {
+        self.storage
+            .start_job(id, chrono::Utc::now().naive_utc(), db)
+            .await
+    }
+
+    pub async fn finish(&self, id: i32, outcome: JobOutcome, db: &Db) -> Result<()> {
+        self.storage.finish_job(id, outcome, db).await
+    }
+
+    pub async fn set_status(&self, id: i32, status: JobStatus, db: &Db) -> Result<()> {
+        self.storage.set_job_status(id, status, db).await
+    }
+
+    pub async fn record_attempt(&self, attempt: &NewJobAttempt, db: &Db) -> Result<()> {
+        self.storage.save_job_attempt(attempt, db).await
+    }
+
+    pub async fn list_attempts(&self, id: i32, db: &Db) -> Result<Vec<JobAttempt>> {
+        self.storage.list_job_attempts(id, db).await
+    }
+
+    pub async fn cancel_queued(&self, id: i32, db: &Db) -> Result<bool> {
+        self.storage
+            .finish_queued_job(id, JobOutcome::cancelled(), db)
+            .await
+    }
+}
+
+#[derive(Clone)]
+pub struct CredentialService {
+    storage: DBStorage,
+}
+
+impl CredentialService {
+    pub fn new(storage: DBStorage) -> Self {
+        CredentialService { storage }
+    }
+
+    pub async fn save(&self, credential: &Credential, db: &Db) -> Result<()> {
+        self.storage.save_credential(credential, db).await
+    }
+
+    pub async fn list(&self, db: &Db) -> Result<Vec<Credential>> {
+        self.storage.list_credentials(db).await
+    }
+
+    pub async fn delete(&self, login: &str, db: &Db) -> Result<()> {
+        self.storage.delete_credential(login, db).await
+    }
+
+    /// Looks up the account a server logs in with, which is nothing for
+    /// anonymous servers.
+    pub async fn for_server(&self, server: &Server, db: &Db) -> Result<Option<Credential>> {
+        if server.login == ANONYMOUS {
+            return Ok(None);
+        }
+        match self.storage.load_credential(&server.login, db).await? {
+            Some(credential) => Ok(Some(credential)),
+            None => Err(InstallFailure {
+                error: InstallError::MissingCredentials,
+                message: format!("no credentials stored for login {}", server.login),
+                exit_code: None,
+            }
+            .into()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WorkshopService {
+    storage: DBStorage,
+    client: workshop::Client,
+}
+
+impl WorkshopService {
+    pub fn new(storage: DBStorage, steam_api_url: &str) -> Self {
+        WorkshopService {
+            storage,
+            client: workshop::Client::new(steam_api_url),
+        }
+    }
+
+    pub async fn list(&self, server_id: i32, db: &Db) -> Result<Vec<WorkshopItem>> {
+        self.storage.list_workshop_items(server_id, db).await
+    }
+
+    /// Adds an item to the end of the load order, leaving it be if the server
+    /// already has it.
+    pub async fn add(&self, server_id: i32, item_id: i64, db: &Db) -> Result<WorkshopItem> {
+        let items = self.list(server_id, db).await?;
+        if let Some(item) = items.iter().find(|item| item.item_id == item_id) {
+            return Ok(item.clone());
+        }
+        let position = items.iter().map(|item| item.position + 1).max();
+        let item = WorkshopItem::pending(server_id, item_id, position.unwrap_or_default());
+        self.storage.save_workshop_item(&item, db).await?;
+        Ok(item)
+    }
+
+    pub async fn reorder(
+        &self,
+        server_id: i32,
+        item_ids: Vec<i64>,
+        db: &Db,
+    ) -> Result<Vec<WorkshopItem>> {
+        workshop::check_order(&self.list(server_id, db).await?, &item_ids)?;
+        self.storage
+            .order_workshop_items(server_id, item_ids, db)
+            .await?;
+        self.list(server_id, db).await
+    }
+
+    /// Replaces the server's mods with a collection's items, looking the
+    /// collection up on Steam unless its details are supplied.
+    pub async fn import_collection(
+        &self,
+        server_id: i32,
+        collection_id: i64,
+        details: Option<CollectionResponse>,
+        db: &Db,
+    ) -> Result<Vec<WorkshopItem>> {
+        let details = match details {
+            Some(details) => details,
+            None => self.client.collection(collection_id).await?,
+        };
+        let item_ids = details.items(collection_id)?;
+        self.storage
+            .replace_workshop_items(server_id, item_ids, db)
+            .await?;
+        self.list(server_id, db).await
+    }
+
+    pub async fn launch_args(&self, server: &Server, db: &Db) -> Result<Vec<String>> {
+        let items = self.list(server.id, db).await?;
+        workshop::launch_args(server, &items)
+    }
+
+    pub async fn remove(&self, server_id: i32, item_id: i64, db: &Db) -> Result<()> {
+        self.storage
+            .delete_workshop_item(server_id, item_id, db)
+            .await
+    }
+
+    /// Records how an item's download went, first putting it into the
+    /// server's mods directory when it has one.
+    pub async fn record(
+        &self,
+        server: &Server,
+        install_dir: &Path,
+        download: &ItemDownload,
+        db: &Db,
+    ) -> Result<()> {
+        let (status, message) = match download {
+            ItemDownload::Downloaded { item_id, path } => match &server.mods_dir {
+                Some(mods_dir) => {
+                    match workshop::link_item(
+                        path,
+                        install_dir,
+                        mods_dir,
+                        *item_id,
+                        server.mods_link,
+                    ) {
+                        Ok(_) => (ItemStatus::Downloaded, None),
+                        Err(e) => (
+                            ItemStatus::Failed,
+                            Some(format!("downloaded but not added to {}: {}", mods_dir, e)),
+                        ),
+                    }
+                }
+                None => (ItemStatus::Downloaded, None),
+            },
+            ItemDownload::Failed { message, .. } => (ItemStatus::Failed, Some(message.clone())),
+        };
+        self.storage
+            .set_workshop_item_status(
+                server.id,
+                download.item_id(),
+                status,
+                message,
+                chrono::Utc::now().naive_utc(),
+                db,
+            )
+            .await
+    }
 }
 
+#[derive(Clone)]
 pub struct InstallService {
     client: install::Client,
     base_dir: String,
+    servers: ServerService,
+    jobs: JobService,
+    credentials: CredentialService,
+    workshop: WorkshopService,
+    events: EventHub,
+    workers: usize,
+    retry: RetryPolicy,
+    requeue_interrupted: bool,
+    min_free_space: u64,
+    snapshots: SnapshotPolicy,
+    locks: ServerLocks,
+    queue: InstallQueue,
+    enqueuing: Arc<AsyncMutex<()>>,
+    running: RunningJobs,
+}
+
+/// A job waiting in the install queue.
+#[derive(Serialize)]
+pub struct QueuedJob {
+    pub position: usize,
+    pub job_id: i32,
+    pub server_id: i32,
+    pub server_name: String,
+    pub kind: JobKind,
+    pub priority: i32,
 }
 
 #[rocket::async_trait]
@@ -51,35 +443,972 @@ impl Fairing for InstallService {
         }
     }
     async fn on_liftoff(&self, rocket: &rocket::Rocket<rocket::Orbit>) {
-        let rx = rocket.state::<InstallQueueRx>().unwrap();
-        let output = rocket.state::<Tx>().unwrap();
-        self.run(&rx.0, &output.0).await.unwrap();
+        match Db::get_one(rocket).await {
+            Some(db) => self.resume(&db).await,
+            None => error!("could not get a database connection to resume jobs"),
+        }
+        // Workers run for the lifetime of the process, so they must not hold
+        // up liftoff.
+        for worker in 0..self.workers {
+            let db = match Db::get_one(rocket).await {
+                Some(db) => db,
+                None => {
+                    error!(
+                        "install worker {} could not get a database connection",
+                        worker
+                    );
+                    continue;
+                }
+            };
+            let service = self.clone();
+            rocket::tokio::spawn(async move { service.run(&db).await });
+        }
     }
 }
 
 impl InstallService {
-    pub fn new(steam_cmd: &str, base_dir: &str) -> Self {
-        let client = install::Client::new(steam_cmd.into());
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        steam_cmd: &str,
+        base_dir: &str,
+        workers: usize,
+        retry: RetryPolicy,
+        timeouts: Timeouts,
+        requeue_interrupted: bool,
+        servers: ServerService,
+        jobs: JobService,
+        credentials: CredentialService,
+        workshop: WorkshopService,
+        events: EventHub,
+    ) -> Self {
+        let client = install::Client::new(steam_cmd).with_timeouts(timeouts);
         InstallService {
-            client: client,
+            client,
             base_dir: base_dir.into(),
+            servers,
+            jobs,
+            credentials,
+            workshop,
+            events,
+            workers: workers.max(1),
+            retry,
+            requeue_interrupted,
+            min_free_space: 0,
+            snapshots: SnapshotPolicy::disabled(),
+            locks: ServerLocks::new(),
+            queue: InstallQueue::new(),
+            enqueuing: Arc::new(AsyncMutex::new(())),
+            running: RunningJobs::new(),
+        }
+    }
+
+    /// Space to keep free on top of what an install is expected to take.
+    pub fn with_min_free_space(mut self, bytes: u64) -> Self {
+        self.min_free_space = bytes;
+        self
+    }
+
+    /// Copy install directories before jobs change them.
+    pub fn with_snapshots(mut self, snapshots: SnapshotPolicy) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    /// Queues a job, or hands back the server's queued job when that already
+    /// does what was asked. A queued update asked to validate becomes a
+    /// validate.
+    pub async fn enqueue(&self, server: Server, kind: JobKind, db: &Db) -> Result<Job> {
+        // Held so that two requests for the same server can not both miss
+        // each other's job.
+        let _enqueuing = self.enqueuing.lock().await;
+        // Includes requests a worker has taken but not started, which are as
+        // good as queued.
+        let waiting: Vec<_> = self
+            .queue
+            .waiting()
+            .into_iter()
+            .filter(|r| r.server.id == server.id)
+            .collect();
+        if let Some(request) = waiting.iter().find(|r| r.kind.covers(kind)) {
+            debug!(
+                "{} of {} is already queued as job {}",
+                kind, server.name, request.job_id
+            );
+            return self.jobs.get_job(request.job_id, db).await;
+        }
+        if let Some(request) = waiting.iter().find(|r| kind.covers(r.kind)) {
+            // Only a request no worker has taken can still be changed.
+            if self.queue.set_kind(request.job_id, kind)
+                && self.jobs.set_queued_kind(request.job_id, kind, db).await?
+            {
+                debug!(
+                    "queued job {} of {} is now a {}",
+                    request.job_id, server.name, kind
+                );
+                return self.jobs.get_job(request.job_id, db).await;
+            }
+        }
+        let job = self.jobs.new_job(server.id, kind, db).await?;
+        self.queue.push(InstallRequest {
+            job_id: job.id,
+            kind,
+            priority: job.priority,
+            server,
+        });
+        Ok(job)
+    }
+
+    /// The jobs that have not started: first those a worker is about to
+    /// start, then the rest in the order they will be picked up. A job whose
+    /// server is busy is passed over until the server is free.
+    pub fn queued(&self) -> Vec<QueuedJob> {
+        self.queue
+            .waiting()
+            .into_iter()
+            .enumerate()
+            .map(|(position, request)| QueuedJob {
+                position,
+                job_id: request.job_id,
+                server_id: request.server.id,
+                server_name: request.server.name,
+                kind: request.kind,
+                priority: request.priority,
+            })
+            .collect()
+    }
+
+    pub async fn set_priority(&self, job_id: i32, priority: i32, db: &Db) -> Result<()> {
+        if !self.queue.set_priority(job_id, priority) {
+            anyhow::bail!("job {} is not queued", job_id);
+        }
+        self.jobs.set_priority(job_id, priority, db).await
+    }
+
+    pub async fn move_to_front(&self, job_id: i32, db: &Db) -> Result<()> {
+        match self.queue.move_to_front(job_id) {
+            Some(priority) => self.jobs.set_priority(job_id, priority, db).await,
+            None => anyhow::bail!("job {} is not queued", job_id),
+        }
+    }
+
+    /// Hands an operator's Steam Guard code to a job waiting on one.
+    pub async fn submit_guard_code(&self, job_id: i32, code: &str, db: &Db) -> Result<Job> {
+        let job = self.jobs.get_job(job_id, db).await?;
+        if job.status != JobStatus::AwaitingGuardCode {
+            anyhow::bail!("job {} is not waiting for a Steam Guard code", job_id);
+        }
+        if !self.running.submit_guard_code(job_id, code.trim()) {
+            anyhow::bail!("job {} is not able to take a Steam Guard code", job_id);
+        }
+        Ok(job)
+    }
+
+    /// Takes a queued job out of the queue, or stops the steamcmd process of
+    /// a running one.
+    pub async fn cancel(&self, job_id: i32, db: &Db) -> Result<Job> {
+        let job = self.jobs.get_job(job_id, db).await?;
+        if job.status.is_finished() {
+            anyhow::bail!("job {} has already {}", job_id, job.status);
         }
+        // A job that is neither queued nor held by a worker was lost, so there
+        // is nothing to stop and it only needs recording.
+        if self.queue.remove(job_id).is_some() || !self.running.cancel(job_id) {
+            self.jobs.cancel_queued(job_id, db).await?;
+            self.events.close_job(job_id);
+        }
+        self.jobs.get_job(job_id, db).await
     }
 
-    pub async fn run(
+    /// Picks up the jobs a previous run of the manager left behind: queued
+    /// ones go back on the queue, and ones it was in the middle of are marked
+    /// interrupted, then queued again as new jobs if configured to.
+    pub async fn resume(&self, db: &Db) {
+        let jobs = match self.jobs.list_unfinished(db).await {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                error!("could not load unfinished jobs: {}", e);
+                return;
+            }
+        };
+        for job in jobs {
+            if let Err(e) = self.resume_job(&job, db).await {
+                error!("could not resume job {}: {}", job.id, e);
+                if let Err(e) = self.jobs.finish(job.id, JobOutcome::failed(&e), db).await {
+                    error!("could not record result of job {}: {}", job.id, e)
+                }
+            }
+        }
+    }
+
+    async fn resume_job(&self, job: &Job, db: &Db) -> Result<()> {
+        let server = self.servers.get_server(job.server_id, db).await?;
+        if job.status == JobStatus::Queued {
+            info!(
+                "resuming queued {} of {} as job {}",
+                job.kind, server.name, job.id
+            );
+            self.queue.push(InstallRequest {
+                job_id: job.id,
+                kind: job.kind,
+                priority: job.priority,
+                server,
+            });
+            return Ok(());
+        }
+        let requeued = match self.requeue_interrupted {
+            true => Some(self.enqueue(server.clone(), job.kind, db).await?),
+            false => None,
+        };
+        warn!(
+            "job {} to {} {} was interrupted by a restart{}",
+            job.id,
+            job.kind,
+            server.name,
+            match &requeued {
+                Some(requeued) => format!(", queued again as job {}", requeued.id),
+                None => String::new(),
+            }
+        );
+        self.jobs
+            .finish(job.id, JobOutcome::interrupted(requeued.map(|j| j.id)), db)
+            .await
+    }
+
+    pub async fn run(&self, db: &Db) {
+        loop {
+            let (request, server_guard) = self.queue.pop(&self.locks).await;
+            debug!(
+                "Recieved request to {} {:?} as job {}",
+                request.kind, request.server, request.job_id
+            );
+            let (cancel, guard_codes) = self.running.register(request.job_id);
+            // Cancelling is left to the install, which has steamcmd's whole
+            // process group to kill rather than only the process itself.
+            let outcome = self.install(&request, &cancel, guard_codes, db).await;
+            self.running.remove(request.job_id);
+            if let Some(outcome) = outcome {
+                if let Err(e) = self.jobs.finish(request.job_id, outcome, db).await {
+                    error!("could not record result of job {}: {}", request.job_id, e)
+                }
+            }
+            self.events.close_job(request.job_id);
+            // Held until the job is recorded so that a second job for the same
+            // server never touches the install directory at the same time.
+            drop(server_guard);
+            self.queue.release(request.job_id);
+        }
+    }
+
+    /// Refuses an install that would not fit, leaving `min_free_space` to
+    /// spare. Updates are sized by the latest build as of the last update
+    /// check, or as Steam has it now for a server that was never checked,
+    /// and pinned installs by the depots they download. A snapshot taken
+    /// first is counted as a full copy of the install.
+    async fn check_space(&self, server: &Server, pins: &[DepotPin], db: &Db) -> Result<()> {
+        let install_dir = self.servers.install_dir(server);
+        let installed = InstalledBuild::read(&install_dir, server.id).await?;
+        let installed_size = installed.as_ref().map(|build| build.size_on_disk);
+        let latest_size = self
+            .servers
+            .get_server(server.id, db)
+            .await?
+            .latest_size
+            .map(|size| size as u64);
+        // Servers are only checked for updates once installed.
+        let latest_size = match latest_size {
+            Some(size) => Some(size),
+            None => self
+                .client
+                .app_info(server.id)
+                .await?
+                .size(server.beta(), server.target_platform()),
+        };
+        let expected = match pins.is_empty() {
+            true => disk::space_needed(latest_size.or(installed_size), installed_size),
+            // download_depot fetches whole depots before they are moved over
+            // the installed files.
+            false => installed
+                .as_ref()
+                .and_then(|build| depots::pinned_size(pins, &build.depots))
+                .or(installed_size)
+                .or(latest_size)
+                .unwrap_or(0),
+        };
+        let snapshot_size = match &installed {
+            Some(build)
+                if self.snapshots.is_enabled() && build.state.contains(&"fully_installed") =>
+            {
+                build.size_on_disk
+            }
+            _ => 0,
+        };
+        let needed = expected + snapshot_size + self.min_free_space;
+        let usage = DiskUsage::of(&install_dir)?;
+        debug!(
+            "{} needs {} bytes, {} are free",
+            server.name, needed, usage.free_bytes
+        );
+        if usage.free_bytes < needed {
+            return Err(InstallFailure {
+                error: InstallError::InsufficientSpace,
+                message: format!(
+                    "installing {} needs about {} free in {} but only {} is",
+                    server.name,
+                    disk::gigabytes(needed),
+                    self.base_dir,
+                    disk::gigabytes(usage.free_bytes)
+                ),
+                exit_code: None,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Moves depots that `download_depot` left in steamcmd's own folder into
+    /// the server's install directory.
+    async fn place_depots(&self, server: &Server, depots: &[DepotDownload]) -> Result<()> {
+        let install_dir = Path::new(&self.base_dir).join(&server.install_dir);
+        for depot in depots {
+            debug!(
+                "moving depot {} from {} into {}",
+                depot.depot_id,
+                depot.path.display(),
+                install_dir.display()
+            );
+            let (depot, install_dir) = (depot.clone(), install_dir.clone());
+            tokio::task::spawn_blocking(move || depot.move_into(&install_dir)).await??;
+        }
+        Ok(())
+    }
+
+    /// Copies a fully installed server before a job changes its files, then
+    /// removes the snapshots past those kept.
+    async fn snapshot(&self, request: &InstallRequest, db: &Db) -> Result<Option<Snapshot>> {
+        if !self.snapshots.is_enabled() || !request.kind.writes_files() {
+            return Ok(None);
+        }
+        let server = &request.server;
+        let install_dir = self.servers.install_dir(server);
+        let installed = match InstalledBuild::read(&install_dir, server.id).await? {
+            Some(installed) if installed.state.contains(&"fully_installed") => installed,
+            _ => {
+                debug!(
+                    "{} is not fully installed, not taking a snapshot",
+                    server.name
+                );
+                return Ok(None);
+            }
+        };
+        let mode = self.snapshots.mode;
+        let path = self.snapshots.path(server.id, request.job_id);
+        info!(
+            "taking {} snapshot of {} at {}",
+            mode,
+            server.name,
+            path.display()
+        );
+        let taken = tokio::task::spawn_blocking({
+            let path = path.clone();
+            move || snapshot::take(mode, &install_dir, &path)
+        })
+        .await?;
+        if let Err(e) = taken {
+            return Err(InstallFailure {
+                error: InstallError::SnapshotFailed,
+                message: format!("could not take a snapshot of {}: {}", server.name, e),
+                exit_code: None,
+            }
+            .into());
+        }
+        let snapshot = NewSnapshot {
+            server_id: server.id,
+            job_id: request.job_id,
+            mode,
+            path: path.display().to_string(),
+            build_id: Some(installed.build_id as i64),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        let snapshot = self.servers.save_snapshot(&snapshot, db).await?;
+        if let Err(e) = self.expire_snapshots(server, db).await {
+            warn!("could not remove old snapshots of {}: {}", server.name, e)
+        }
+        Ok(Some(snapshot))
+    }
+
+    async fn expire_snapshots(&self, server: &Server, db: &Db) -> Result<()> {
+        let snapshots = self.servers.snapshots(server.id, db).await?;
+        for expired in self.snapshots.expired(&snapshots) {
+            debug!("removing snapshot {} of {}", expired.id, server.name);
+            let path = PathBuf::from(&expired.path);
+            tokio::task::spawn_blocking(move || snapshot::remove(&path)).await??;
+            self.servers.delete_snapshot(expired.id, db).await?;
+        }
+        Ok(())
+    }
+
+    async fn restore(&self, server: &Server, snapshot: &Snapshot, db: &Db) -> Result<()> {
+        info!("restoring {} from snapshot {}", server.name, snapshot.id);
+        let install_dir = self.servers.install_dir(server);
+        let restored = snapshot.clone();
+        tokio::task::spawn_blocking(move || snapshot::restore(&restored, &install_dir)).await??;
+        self.servers.installed(server, db).await
+    }
+
+    /// Puts a server's files back as they were before an update, from the
+    /// given snapshot or else the newest. Refused while a job is working on
+    /// the server.
+    pub async fn rollback(&self, id: i32, snapshot_id: Option<i32>, db: &Db) -> Result<Snapshot> {
+        let server = self.servers.get_server(id, db).await?;
+        let server_guard = self.locks.try_lock(id).ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} has a job running, try again once it is done",
+                server.name
+            )
+        })?;
+        let rolled_back = async {
+            let snapshots = self.servers.snapshots(id, db).await?;
+            let snapshot = match snapshot_id {
+                Some(snapshot_id) => snapshots.into_iter().find(|s| s.id == snapshot_id),
+                None => snapshots.into_iter().next(),
+            }
+            .ok_or_else(|| anyhow::anyhow!("{} has no snapshot to roll back to", server.name))?;
+            self.restore(&server, &snapshot, db).await?;
+            Ok(snapshot)
+        }
+        .await;
+        // Jobs for the server were left queued while it was locked.
+        drop(server_guard);
+        self.queue.wake();
+        rolled_back
+    }
+
+    async fn record_downloads(&self, server: &Server, downloads: &[ItemDownload], db: &Db) {
+        let install_dir = Path::new(&self.base_dir).join(&server.install_dir);
+        for download in downloads {
+            if let Err(e) = self
+                .workshop
+                .record(server, &install_dir, download, db)
+                .await
+            {
+                error!(
+                    "could not record workshop item {}: {}",
+                    download.item_id(),
+                    e
+                )
+            }
+        }
+    }
+
+    async fn install(
         &self,
-        rx: &flume::Receiver<Server>,
-        output: &flume::Sender<String>,
-    ) -> Result<(), anyhow::Error> {
-        for server in rx {
-            debug!("Recieved request to install {:?}", server);
-            if let Err(e) = self.client.install(&self.base_dir, &server, output).await {
-                error!("problem installing: {}", e)
+        request: &InstallRequest,
+        cancel: &Notify,
+        guard_codes: mpsc::Receiver<String>,
+        db: &Db,
+    ) -> Option<JobOutcome> {
+        match self.jobs.start(request.job_id, db).await {
+            Ok(true) => {}
+            Ok(false) => {
+                debug!("job {} was cancelled before it started", request.job_id);
+                return None;
+            }
+            Err(e) => error!("could not mark job {} as running: {}", request.job_id, e),
+        }
+        self.queue.started(request.job_id);
+        let credential = match self.credentials.for_server(&request.server, db).await {
+            Ok(credential) => credential,
+            Err(e) => {
+                error!("cannot log in for job {}: {}", request.job_id, e);
+                return Some(JobOutcome::failed(&e));
+            }
+        };
+        let pins = match self.servers.pins(request.server.id, db).await {
+            Ok(pins) => pins,
+            Err(e) => {
+                error!("cannot list depot pins for job {}: {}", request.job_id, e);
+                return Some(JobOutcome::failed(&e));
+            }
+        };
+        if request.kind.writes_files() {
+            if let Err(e) = self.check_space(&request.server, &pins, db).await {
+                error!("not starting job {}: {}", request.job_id, e);
+                return Some(JobOutcome::failed(&e));
+            }
+        }
+        let snapshot = match self.snapshot(request, db).await {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                error!("not starting job {}: {}", request.job_id, e);
+                return Some(JobOutcome::failed(&e));
+            }
+        };
+        let shared_secret = credential.as_ref().and_then(|c| c.shared_secret.clone());
+        let (guard, mut waiting) = SteamGuard::new(shared_secret, guard_codes);
+        let items = match self.workshop.list(request.server.id, db).await {
+            Ok(items) => items.iter().map(|item| item.item_id).collect::<Vec<_>>(),
+            Err(e) => {
+                error!(
+                    "cannot list workshop items for job {}: {}",
+                    request.job_id, e
+                );
+                return Some(JobOutcome::failed(&e));
+            }
+        };
+        let output = self.events.publisher(request.job_id, request.server.id);
+        let script = install::runscript(
+            &self.base_dir,
+            &request.server,
+            request.kind,
+            credential.as_ref(),
+            &items,
+            &pins,
+        );
+        info!(
+            "job {} runs steamcmd script:\n{}",
+            request.job_id,
+            script.redacted()
+        );
+        let attempts = async {
+            // Dropped once there are no more runs, which ends `track_guard`.
+            let mut guard = guard;
+            let mut attempt = 1;
+            loop {
+                let started_at = chrono::Utc::now().naive_utc();
+                let mut downloads = vec![];
+                let result = self
+                    .client
+                    .install(
+                        &script,
+                        request.kind,
+                        &mut guard,
+                        &output,
+                        cancel,
+                        &mut downloads,
+                    )
+                    .await;
+                self.record_downloads(&request.server, &downloads, db).await;
+                let outcome = match &result {
+                    Ok(finished) => JobOutcome::succeeded(finished),
+                    Err(e) => JobOutcome::failed(e),
+                };
+                let record = NewJobAttempt::new(request.job_id, attempt, started_at, &outcome);
+                if let Err(e) = self.jobs.record_attempt(&record, db).await {
+                    error!(
+                        "could not record attempt {} of job {}: {}",
+                        attempt, request.job_id, e
+                    )
+                }
+
+                let (e, delay) = match result {
+                    Err(e) => match self.retry.delay(attempt + 1, &e) {
+                        Some(delay) => (e, delay),
+                        None => return Err(e),
+                    },
+                    finished => return finished,
+                };
+                warn!(
+                    "attempt {} of job {} failed, retrying in {}s: {}",
+                    attempt,
+                    request.job_id,
+                    delay.as_secs(),
+                    e
+                );
+                attempt += 1;
+                output.send(InstallEvent::Retrying {
+                    attempt,
+                    delay_seconds: delay.as_secs(),
+                    error: outcome.error.unwrap_or(InstallError::Unknown),
+                });
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = cancel.notified() => return Err(InstallFailure::cancelled().into()),
+                }
+                guard.new_attempt();
+            }
+        };
+        // Mirrors whether steamcmd is stuck on a Steam Guard prompt onto the
+        // job, until the install finishes and drops its end.
+        let track_guard = async {
+            while waiting.changed().await.is_ok() {
+                let status = match *waiting.borrow() {
+                    true => JobStatus::AwaitingGuardCode,
+                    false => JobStatus::Running,
+                };
+                if let Err(e) = self.jobs.set_status(request.job_id, status, db).await {
+                    error!("could not mark job {} as {}: {}", request.job_id, status, e)
+                }
+            }
+        };
+        let (result, _) = tokio::join!(attempts, track_guard);
+        Some(match result {
+            Ok(finished) => {
+                if let Err(e) = self.place_depots(&request.server, &finished.depots).await {
+                    error!(
+                        "could not put depots in place for job {}: {}",
+                        request.job_id, e
+                    );
+                    return Some(JobOutcome::failed(&e));
+                }
+                if request.kind.writes_files() {
+                    if let Err(e) = self.servers.installed(&request.server, db).await {
+                        error!("could not record install of {}: {}", request.server.name, e)
+                    }
+                }
+                JobOutcome::succeeded(&finished)
+            }
+            Err(e) => {
+                error!("problem installing: {}", e);
+                if let Some(snapshot) = snapshot.filter(|_| self.snapshots.restore_failed) {
+                    match self.restore(&request.server, &snapshot, db).await {
+                        Ok(()) => info!(
+                            "rolled {} back to snapshot {} after job {} failed",
+                            request.server.name, snapshot.id, request.job_id
+                        ),
+                        Err(e) => error!(
+                            "could not roll {} back to snapshot {}: {}",
+                            request.server.name, snapshot.id, e
+                        ),
+                    }
+                }
+                JobOutcome::failed(&e)
+            }
+        })
+    }
+}
+
+/// Periodically asks Steam for the latest build of every installed server.
+#[derive(Clone)]
+pub struct UpdateService {
+    client: install::Client,
+    servers: ServerService,
+    interval: Option<Duration>,
+}
+
+#[rocket::async_trait]
+impl Fairing for UpdateService {
+    fn info(&self) -> rocket::fairing::Info {
+        Info {
+            name: "Check servers for updates",
+            kind: Kind::Liftoff,
+        }
+    }
+    async fn on_liftoff(&self, rocket: &rocket::Rocket<rocket::Orbit>) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let db = match Db::get_one(rocket).await {
+            Some(db) => db,
+            None => {
+                error!("could not get a database connection to check for updates");
+                return;
+            }
+        };
+        let service = self.clone();
+        rocket::tokio::spawn(async move {
+            loop {
+                service.check_all(&db).await;
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+}
+
+impl UpdateService {
+    pub fn new(
+        steam_cmd: &str,
+        timeouts: Timeouts,
+        servers: ServerService,
+        interval: Option<Duration>,
+    ) -> Self {
+        UpdateService {
+            client: install::Client::new(steam_cmd).with_timeouts(timeouts),
+            servers,
+            interval,
+        }
+    }
+
+    /// Compares the build a server has installed with the latest on its
+    /// branch, leaving servers that were never installed alone.
+    pub async fn check(&self, server: &Server, db: &Db) -> Result<BuildStatus> {
+        let install_dir = self.servers.install_dir(server);
+        let installed = match InstalledBuild::read(&install_dir, server.id).await? {
+            Some(installed) => installed,
+            None => return Ok(BuildStatus::new(None, None, None, None)),
+        };
+        let info = self.client.app_info(server.id).await?;
+        let latest = info.build_id(server.beta());
+        if latest.is_none() {
+            warn!(
+                "steamcmd reported no build for {} on branch {}",
+                server.name,
+                server.beta().unwrap_or("public")
+            );
+        }
+        let status = BuildStatus::new(
+            Some(&installed),
+            latest,
+            info.size(server.beta(), server.target_platform()),
+            Some(chrono::Utc::now().naive_utc()),
+        );
+        let status = match self.servers.pins(server.id, db).await?.is_empty() {
+            true => status,
+            false => status.pinned(),
+        };
+        self.servers
+            .set_build_status(server.id, status.clone(), db)
+            .await?;
+        Ok(status)
+    }
+
+    pub async fn check_all(&self, db: &Db) {
+        let servers = match self.servers.list_servers(db).await {
+            Ok(servers) => servers,
+            Err(e) => {
+                error!("could not list servers to check for updates: {}", e);
+                return;
+            }
+        };
+        for server in servers {
+            match self.check(&server, db).await {
+                Ok(status) if status.update_available => info!(
+                    "{} has build {:?} installed, {:?} is available",
+                    server.name, status.installed_build_id, status.latest_build_id
+                ),
+                Ok(_) => {}
+                Err(e) => warn!("could not check {} for updates: {}", server.name, e),
+            }
+        }
+    }
+}
+
+/// Reports on the disks servers are installed to.
+#[derive(Clone)]
+pub struct StorageService {
+    base_dirs: Vec<PathBuf>,
+}
+
+impl StorageService {
+    pub fn new(base_dir: &str) -> Self {
+        StorageService {
+            base_dirs: vec![base_dir.into()],
+        }
+    }
+
+    pub fn usage(&self) -> Result<Vec<DiskUsage>> {
+        self.base_dirs
+            .iter()
+            .map(|dir| DiskUsage::of(dir))
+            .collect()
+    }
+}
+
+/// How long `when_available` waits before trying again when an update left
+/// the server behind, e.g. because it failed.
+const WHEN_AVAILABLE_RETRY_HOURS: i64 = 1;
+
+/// Queues the updates that servers' update policies call for.
+#[derive(Clone)]
+pub struct UpdateScheduler {
+    servers: ServerService,
+    jobs: JobService,
+    installs: InstallService,
+    tick: Duration,
+}
+
+#[rocket::async_trait]
+impl Fairing for UpdateScheduler {
+    fn info(&self) -> rocket::fairing::Info {
+        Info {
+            name: "Run scheduled updates",
+            kind: Kind::Liftoff,
+        }
+    }
+    async fn on_liftoff(&self, rocket: &rocket::Rocket<rocket::Orbit>) {
+        let db = match Db::get_one(rocket).await {
+            Some(db) => db,
+            None => {
+                error!("could not get a database connection to schedule updates");
+                return;
+            }
+        };
+        let service = self.clone();
+        rocket::tokio::spawn(async move {
+            loop {
+                service.run_due(chrono::Utc::now().naive_utc(), &db).await;
+                tokio::time::sleep(service.tick).await;
+            }
+        });
+    }
+}
+
+impl UpdateScheduler {
+    pub fn new(servers: ServerService, jobs: JobService, installs: InstallService) -> Self {
+        UpdateScheduler {
+            servers,
+            jobs,
+            installs,
+            tick: Duration::from_secs(60),
+        }
+    }
+
+    pub async fn run_due(&self, now: chrono::NaiveDateTime, db: &Db) {
+        let servers = match self.servers.list_servers(db).await {
+            Ok(servers) => servers,
+            Err(e) => {
+                error!("could not list servers to schedule updates: {}", e);
+                return;
+            }
+        };
+        for server in servers {
+            if let Err(e) = self.run_server(&server, now, db).await {
+                warn!("could not schedule an update of {}: {}", server.name, e);
+            }
+        }
+    }
+
+    async fn run_server(&self, server: &Server, now: chrono::NaiveDateTime, db: &Db) -> Result<()> {
+        let schedule = Schedule::for_server(server)?;
+        let mut next = match (schedule.policy(), server.next_update_at) {
+            (UpdatePolicy::Never, _) => None,
+            (UpdatePolicy::WhenAvailable, _) if !server.update_available => None,
+            (_, Some(next)) => Some(next),
+            (_, None) => schedule.next_run(now, server.update_available),
+        };
+        if matches!(next, Some(due) if due <= now) {
+            next = match schedule.in_window(now) {
+                true => {
+                    self.start_update(server, db).await?;
+                    let after = match schedule.policy() {
+                        UpdatePolicy::WhenAvailable => {
+                            now + chrono::Duration::hours(WHEN_AVAILABLE_RETRY_HOURS)
+                        }
+                        _ => now,
+                    };
+                    schedule.next_run(after, server.update_available)
+                }
+                // Missed the window, e.g. while the manager was not running.
+                false => Some(schedule.window_open(now)),
             };
         }
+        if next != server.next_update_at {
+            self.servers.set_next_update(server.id, next, db).await?;
+        }
         Ok(())
     }
+
+    async fn start_update(&self, server: &Server, db: &Db) -> Result<()> {
+        let pins = self.servers.pins(server.id, db).await?;
+        if !pins.is_empty() {
+            info!(
+                "not starting the scheduled update of {}, {} of its depots are pinned",
+                server.name,
+                pins.len()
+            );
+            return Ok(());
+        }
+        let jobs = self.jobs.list_jobs(server.id, db).await?;
+        if let Some(job) = jobs.iter().find(|job| !job.status.is_finished()) {
+            info!(
+                "not starting the scheduled update of {}, job {} is still {}",
+                server.name, job.id, job.status
+            );
+            return Ok(());
+        }
+        let job = self
+            .installs
+            .enqueue(server.clone(), JobKind::Update, db)
+            .await?;
+        info!(
+            "queued scheduled update of {} as job {}",
+            server.name, job.id
+        );
+        Ok(())
+    }
+}
+
+/// Keeps steamcmd itself installed.
+#[derive(Clone)]
+pub struct SteamCmdService {
+    storage: DBStorage,
+    bootstrap: Bootstrap,
+    bootstrap_on_launch: bool,
+    installing: Arc<AsyncMutex<()>>,
+}
+
+#[derive(Serialize)]
+pub struct SteamCmdStatus {
+    pub location: String,
+    pub installed: bool,
+    pub latest: Option<SteamCmdInstall>,
+}
+
+#[rocket::async_trait]
+impl Fairing for SteamCmdService {
+    fn info(&self) -> rocket::fairing::Info {
+        Info {
+            name: "Check for steamcmd on launch",
+            kind: Kind::Liftoff,
+        }
+    }
+    async fn on_liftoff(&self, rocket: &rocket::Rocket<rocket::Orbit>) {
+        if self.bootstrap.is_installed() {
+            return;
+        }
+        if !self.bootstrap_on_launch {
+            warn!(
+                "steamcmd is missing from {}, install it with POST /steamcmd/install",
+                self.bootstrap.location().display()
+            );
+            return;
+        }
+        let db = match Db::get_one(rocket).await {
+            Some(db) => db,
+            None => {
+                error!("could not get a database connection to install steamcmd");
+                return;
+            }
+        };
+        let service = self.clone();
+        rocket::tokio::spawn(async move {
+            match service.install(&db).await {
+                Ok(install) => info!("installed steamcmd {:?}", install.version),
+                Err(e) => error!("could not install steamcmd: {}", e),
+            }
+        });
+    }
 }
+
+impl SteamCmdService {
+    pub fn new(storage: DBStorage, bootstrap: Bootstrap, bootstrap_on_launch: bool) -> Self {
+        SteamCmdService {
+            storage,
+            bootstrap,
+            bootstrap_on_launch,
+            installing: Arc::new(AsyncMutex::new(())),
+        }
+    }
+
+    pub async fn status(&self, db: &Db) -> Result<SteamCmdStatus> {
+        Ok(SteamCmdStatus {
+            location: self.bootstrap.location().display().to_string(),
+            installed: self.bootstrap.is_installed(),
+            latest: self.storage.latest_steamcmd_install(db).await?,
+        })
+    }
+
+    /// Downloads steamcmd afresh, whether or not it is already there.
+    pub async fn install(&self, db: &Db) -> Result<SteamCmdInstall> {
+        let _installing = match self.installing.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => anyhow::bail!("steamcmd is already being installed"),
+        };
+        let install = self.bootstrap.install().await?;
+        self.storage.save_steamcmd_install(&install, db).await
+    }
+}
+
 pub struct SteamAppsService {
     client: steam_apps::Client,
 }
@@ -98,3 +1427,179 @@ impl SteamAppsService {
         self.client.search(name, db).await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::*;
+    use crate::db;
+
+    fn install_service(dir: &Path, steamcmd: &str) -> InstallService {
+        let storage = DBStorage {};
+        let base_dir = dir.display().to_string();
+        InstallService::new(
+            steamcmd,
+            &base_dir,
+            1,
+            RetryPolicy::new(0, Duration::from_secs(1)),
+            Timeouts::default(),
+            false,
+            ServerService::new(storage.clone(), &base_dir),
+            JobService::new(storage.clone()),
+            CredentialService::new(storage.clone()),
+            WorkshopService::new(storage, "http://localhost"),
+            EventHub::new(),
+        )
+    }
+
+    /// A steamcmd that reports a public build of the given size.
+    fn fake_steamcmd(dir: &Path, size: u64) -> anyhow::Result<String> {
+        let path = dir.join("steamcmd.sh");
+        fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\n\
+                 echo 'AppID : 740, change number : 20194432/0'\n\
+                 echo '\"740\"'\n\
+                 echo '{{'\n\
+                 echo '\"depots\" {{ \"741\" {{ \"manifests\" {{ \"public\" {{ \"size\" \"{}\" }} }} }} }}'\n\
+                 echo '}}'\n",
+                size
+            ),
+        )?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        Ok(path.display().to_string())
+    }
+
+    fn insufficient_space(result: Result<()>) -> bool {
+        matches!(
+            result.map_err(|e| e.downcast::<InstallFailure>().map(|f| f.error)),
+            Err(Ok(InstallError::InsufficientSpace))
+        )
+    }
+
+    #[tokio::test]
+    async fn test_check_space() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (_rocket, db) = db::test_db(dir.path()).await?;
+        let too_big = DiskUsage::of(dir.path())?.free_bytes + (1 << 30);
+        let server = Server::new(740, "csgo", "anonymous", "csgo");
+        let service = install_service(dir.path(), &fake_steamcmd(dir.path(), too_big)?);
+        service.servers.new_server(&server, &db).await?;
+
+        // Never installed, so never checked for updates either.
+        assert!(insufficient_space(
+            service.check_space(&server, &[], &db).await
+        ));
+        let service = install_service(dir.path(), &fake_steamcmd(dir.path(), 1)?);
+        service.check_space(&server, &[], &db).await?;
+
+        // Installed at the latest size, so only a snapshot takes more.
+        let manifest = InstalledBuild::path(&service.servers.install_dir(&server), server.id);
+        fs::create_dir_all(manifest.parent().unwrap())?;
+        fs::write(
+            &manifest,
+            format!(
+                "\"AppState\" {{ \"appid\" \"740\" \"StateFlags\" \"4\" \
+                 \"SizeOnDisk\" \"{}\" \"buildid\" \"42\" }}",
+                too_big
+            ),
+        )?;
+        let service = install_service(dir.path(), &fake_steamcmd(dir.path(), too_big)?);
+        service.check_space(&server, &[], &db).await?;
+        let service = service.with_snapshots(SnapshotPolicy::new(
+            snapshot::SnapshotMode::Tar,
+            &dir.path().join("snapshots"),
+            1,
+            false,
+        ));
+        assert!(insufficient_space(
+            service.check_space(&server, &[], &db).await
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_moved_job_stays_first_after_restart() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (_rocket, db) = db::test_db(dir.path()).await?;
+        let service = install_service(dir.path(), "steamcmd.sh");
+        let mut jobs = vec![];
+        for id in 1..=3 {
+            let server = Server::new(id, &format!("server{}", id), "anonymous", "server");
+            service.servers.new_server(&server, &db).await?;
+            jobs.push(service.enqueue(server, JobKind::Update, &db).await?.id);
+        }
+        service.move_to_front(jobs[2], &db).await?;
+
+        let restarted = install_service(dir.path(), "steamcmd.sh");
+        restarted.resume(&db).await;
+
+        let order: Vec<_> = restarted.queued().iter().map(|job| job.job_id).collect();
+        assert_eq!(vec![jobs[2], jobs[0], jobs[1]], order);
+        Ok(())
+    }
+
+    /// Whether a process is still running, rather than gone or a zombie.
+    fn running(pid: i32) -> bool {
+        fs::read_to_string(format!("/proc/{}/stat", pid)).is_ok_and(|stat| !stat.contains(") Z "))
+    }
+
+    #[tokio::test]
+    async fn test_cancel_kills_process_group() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (rocket, db) = db::test_db(dir.path()).await?;
+        let child = dir.path().join("child.pid");
+        let steamcmd = dir.path().join("steamcmd.sh");
+        // Leaves a process of its own running, as steamcmd does.
+        fs::write(
+            &steamcmd,
+            format!(
+                "#!/bin/sh\nsleep 600 &\necho $! > {}.tmp\nmv {0}.tmp {0}\nwait\n",
+                child.display()
+            ),
+        )?;
+        fs::set_permissions(&steamcmd, fs::Permissions::from_mode(0o755))?;
+        let service = install_service(dir.path(), &steamcmd.display().to_string());
+        let server = Server::new(740, "csgo", "anonymous", "csgo");
+        service.servers.new_server(&server, &db).await?;
+        let job = service.enqueue(server, JobKind::Verify, &db).await?;
+
+        let worker = service.clone();
+        let worker_db = Db::get_one(&rocket).await.unwrap();
+        tokio::spawn(async move { worker.run(&worker_db).await });
+        let pid = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Ok(pid) = fs::read_to_string(&child) {
+                    return pid.trim().parse::<i32>();
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await??;
+        assert!(running(pid));
+
+        service.cancel(job.id, &db).await?;
+        let job = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let job = service.jobs.get_job(job.id, &db).await?;
+                if job.status.is_finished() {
+                    return anyhow::Ok(job);
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await??;
+
+        assert_eq!(JobStatus::Cancelled, job.status);
+        for _ in 0..50 {
+            if !running(pid) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!running(pid));
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    ffi::OsString,
+    fmt, fs,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::schema::snapshots;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::{sql_types::Text, Insertable, Queryable};
+use serde::{Deserialize, Serialize};
+
+/// How a server's install directory is copied before it is updated.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, AsExpression, FromSqlRow,
+)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum SnapshotMode {
+    #[default]
+    None,
+    /// A copy of the install directory, made of copy-on-write clones where
+    /// the filesystem supports them (btrfs, XFS), so that it takes next to no
+    /// space there. Hard links would share their contents with the live
+    /// files, which steamcmd patches in place.
+    Copy,
+    /// A tar archive of the install directory.
+    Tar,
+}
+
+impl SnapshotMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotMode::None => "none",
+            SnapshotMode::Copy => "copy",
+            SnapshotMode::Tar => "tar",
+        }
+    }
+}
+
+impl fmt::Display for SnapshotMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SnapshotMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SnapshotMode::None),
+            "copy" => Ok(SnapshotMode::Copy),
+            "tar" => Ok(SnapshotMode::Tar),
+            other => Err(anyhow::anyhow!("unknown snapshot mode: {}", other)),
+        }
+    }
+}
+
+text_column!(SnapshotMode);
+
+/// A copy of a server's files as they were before an update.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Queryable)]
+pub struct Snapshot {
+    pub id: i32,
+    pub server_id: i32,
+    /// The job that was about to change the files.
+    pub job_id: i32,
+    pub mode: SnapshotMode,
+    pub path: String,
+    pub build_id: Option<i64>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "snapshots"]
+pub struct NewSnapshot {
+    pub server_id: i32,
+    pub job_id: i32,
+    pub mode: SnapshotMode,
+    pub path: String,
+    pub build_id: Option<i64>,
+    pub created_at: NaiveDateTime,
+}
+
+/// Whether and where snapshots are taken, and how many are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotPolicy {
+    pub mode: SnapshotMode,
+    pub dir: PathBuf,
+    /// Snapshots kept for each server, the oldest going first.
+    pub keep: usize,
+    /// Put the snapshot back when the job it was taken for fails.
+    pub restore_failed: bool,
+}
+
+impl SnapshotPolicy {
+    pub fn new(mode: SnapshotMode, dir: &Path, keep: usize, restore_failed: bool) -> Self {
+        SnapshotPolicy {
+            mode,
+            dir: dir.into(),
+            keep: keep.max(1),
+            restore_failed,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        SnapshotPolicy::new(SnapshotMode::None, Path::new("."), 1, false)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.mode != SnapshotMode::None
+    }
+
+    pub fn path(&self, server_id: i32, job_id: i32) -> PathBuf {
+        let name = match self.mode {
+            SnapshotMode::Tar => format!("{}.tar", job_id),
+            _ => job_id.to_string(),
+        };
+        self.dir.join(server_id.to_string()).join(name)
+    }
+
+    /// The snapshots past the newest `keep`, out of a server's snapshots
+    /// listed newest first.
+    pub fn expired<'a>(&self, snapshots: &'a [Snapshot]) -> &'a [Snapshot] {
+        &snapshots[self.keep.min(snapshots.len())..]
+    }
+}
+
+/// Copies an install directory to `path`, leaving nothing behind if it
+/// can not.
+pub fn take(mode: SnapshotMode, install_dir: &Path, path: &Path) -> Result<()> {
+    let taken = match mode {
+        SnapshotMode::None => anyhow::bail!("snapshots are turned off"),
+        SnapshotMode::Copy => copy_dir(install_dir, path),
+        SnapshotMode::Tar => archive(install_dir, path),
+    };
+    if taken.is_err() {
+        remove(path)?;
+    }
+    taken
+}
+
+/// Puts a snapshot in place of the install directory. The snapshot is left
+/// as it was, so it can be restored again.
+pub fn restore(snapshot: &Snapshot, install_dir: &Path) -> Result<()> {
+    let source = Path::new(&snapshot.path);
+    // Built next to the install directory first, so a snapshot that can not
+    // be read leaves the server as it is.
+    let staging = beside(install_dir, "rollback");
+    remove(&staging)?;
+    let restored = match snapshot.mode {
+        SnapshotMode::None => anyhow::bail!("snapshot {} holds no files", snapshot.id),
+        SnapshotMode::Copy => copy_dir(source, &staging),
+        SnapshotMode::Tar => unpack(source, &staging),
+    };
+    if let Err(e) = restored {
+        remove(&staging)?;
+        return Err(e);
+    }
+    // Only ever renamed, so the server always has a whole install directory,
+    // even if the manager stops part way.
+    let old = beside(install_dir, "old");
+    remove(&old)?;
+    let moved_aside = install_dir.exists();
+    if moved_aside {
+        fs::rename(install_dir, &old)?;
+    }
+    if let Err(e) = fs::rename(&staging, install_dir) {
+        if moved_aside {
+            fs::rename(&old, install_dir)?;
+        }
+        return Err(e.into());
+    }
+    // Whatever is left is cleared by the next restore.
+    let _ = remove(&old);
+    Ok(())
+}
+
+/// A path next to `dir`, named after it with an extra extension.
+fn beside(dir: &Path, extension: &str) -> PathBuf {
+    let mut path = OsString::from(dir.as_os_str());
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Deletes a snapshot's files, which may already be gone.
+pub fn remove(path: &Path) -> Result<()> {
+    let removed = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    };
+    Ok(removed?)
+}
+
+fn copy_dir(source: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let to = target.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &to)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &to)?;
+        } else {
+            clone_file(&entry.path(), &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies a file as a copy-on-write clone, or in full where the filesystem
+/// can not clone it.
+fn clone_file(source: &Path, target: &Path) -> Result<()> {
+    let from = fs::File::open(source)?;
+    let to = fs::File::create(target)?;
+    if unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd()) } == 0 {
+        to.set_permissions(from.metadata()?.permissions())?;
+        return Ok(());
+    }
+    drop(to);
+    fs::copy(source, target)?;
+    Ok(())
+}
+
+fn archive(install_dir: &Path, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut builder = tar::Builder::new(fs::File::create(path)?);
+    builder.follow_symlinks(false);
+    builder.append_dir_all(".", install_dir)?;
+    builder.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+fn unpack(path: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target)?;
+    let mut archive = tar::Archive::new(fs::File::open(path)?);
+    archive.set_preserve_permissions(true);
+    archive.unpack(target)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn snapshot(id: i32, mode: SnapshotMode, path: &Path) -> Snapshot {
+        Snapshot {
+            id,
+            server_id: 740,
+            job_id: id,
+            mode,
+            path: path.display().to_string(),
+            build_id: None,
+            created_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_mode_round_trip() -> anyhow::Result<()> {
+        for mode in [SnapshotMode::None, SnapshotMode::Copy, SnapshotMode::Tar] {
+            assert_eq!(mode, mode.as_str().parse()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_take_and_restore() -> anyhow::Result<()> {
+        for mode in [SnapshotMode::Copy, SnapshotMode::Tar] {
+            let dir = tempfile::tempdir()?;
+            let install_dir = dir.path().join("csgo");
+            fs::create_dir_all(install_dir.join("csgo").join("cfg"))?;
+            fs::write(install_dir.join("srcds_run"), "old")?;
+            fs::set_permissions(
+                install_dir.join("srcds_run"),
+                fs::Permissions::from_mode(0o755),
+            )?;
+            fs::write(
+                install_dir.join("csgo").join("cfg").join("server.cfg"),
+                "old",
+            )?;
+            let policy = SnapshotPolicy::new(mode, &dir.path().join("snapshots"), 3, false);
+            let path = policy.path(740, 1);
+
+            take(mode, &install_dir, &path)?;
+            // Written in place, as steamcmd patches files.
+            fs::write(install_dir.join("srcds_run"), "new")?;
+            fs::write(install_dir.join("added.txt"), "new")?;
+            restore(&snapshot(1, mode, &path), &install_dir)?;
+
+            assert_eq!("old", fs::read_to_string(install_dir.join("srcds_run"))?);
+            assert_eq!(
+                0o755,
+                fs::metadata(install_dir.join("srcds_run"))?
+                    .permissions()
+                    .mode()
+                    & 0o777
+            );
+            assert_eq!(
+                "old",
+                fs::read_to_string(install_dir.join("csgo").join("cfg").join("server.cfg"))?
+            );
+            assert!(!install_dir.join("added.txt").exists());
+            assert!(path.exists());
+            assert!(!dir.path().join("csgo.old").exists());
+            assert!(!dir.path().join("csgo.rollback").exists());
+
+            remove(&path)?;
+            assert!(!path.exists());
+            remove(&path)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_restore_keeps_install() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let install_dir = dir.path().join("csgo");
+        fs::create_dir_all(&install_dir)?;
+        fs::write(install_dir.join("srcds_run"), "new")?;
+
+        let missing = snapshot(1, SnapshotMode::Tar, &dir.path().join("1.tar"));
+        assert!(restore(&missing, &install_dir).is_err());
+        assert_eq!("new", fs::read_to_string(install_dir.join("srcds_run"))?);
+        assert!(!dir.path().join("csgo.rollback").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired() {
+        let policy = SnapshotPolicy::new(SnapshotMode::Tar, Path::new("/srv/snapshots"), 2, false);
+        let snapshots: Vec<_> = (1..=3)
+            .rev()
+            .map(|id| snapshot(id, SnapshotMode::Tar, &policy.path(740, id)))
+            .collect();
+
+        assert_eq!(&snapshots[2..], policy.expired(&snapshots));
+        assert!(policy.expired(&snapshots[..2]).is_empty());
+        assert_eq!(
+            PathBuf::from("/srv/snapshots/740/3.tar"),
+            policy.path(740, 3)
+        );
+        // Taking snapshots at all means keeping at least one.
+        assert_eq!(
+            1,
+            SnapshotPolicy::new(SnapshotMode::Tar, Path::new("."), 0, false).keep
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use tokio::sync::{mpsc, watch};
+
+const CODE_CHARS: &[u8] = b"23456789BCDFGHJKMNPQRTVWXY";
+const CODE_LENGTH: usize = 5;
+const PERIOD_SECS: u64 = 30;
+
+/// steamcmd leaves the cursor on these prompts while it waits for a code.
+const PROMPTS: &[&str] = &["Steam Guard code:", "Two-factor code:"];
+
+pub fn is_prompt(text: &str) -> bool {
+    let text = text.trim_end();
+    PROMPTS.iter().any(|prompt| text.ends_with(prompt))
+}
+
+/// Generates the mobile authenticator code for a base64 shared secret, the
+/// same way the Steam app does.
+pub fn generate_code(shared_secret: &str, unix_time: u64) -> Result<String> {
+    let secret = base64::decode(shared_secret.trim())?;
+    let mut mac = Hmac::<Sha1>::new_from_slice(&secret)?;
+    mac.update(&(unix_time / PERIOD_SECS).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[19] & 0x0f) as usize;
+    let mut value = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+
+    let mut code = String::with_capacity(CODE_LENGTH);
+    for _ in 0..CODE_LENGTH {
+        code.push(CODE_CHARS[value as usize % CODE_CHARS.len()] as char);
+        value /= CODE_CHARS.len() as u32;
+    }
+    Ok(code)
+}
+
+/// Where an install gets Steam Guard codes from: generated from a stored
+/// shared secret the first time steamcmd asks, and from an operator after
+/// that.
+pub struct SteamGuard {
+    shared_secret: Option<String>,
+    generated: bool,
+    codes: mpsc::Receiver<String>,
+    waiting: watch::Sender<bool>,
+}
+
+impl SteamGuard {
+    /// The returned receiver reports whether the install is waiting on an
+    /// operator for a code.
+    pub fn new(
+        shared_secret: Option<String>,
+        codes: mpsc::Receiver<String>,
+    ) -> (Self, watch::Receiver<bool>) {
+        let (waiting, rx) = watch::channel(false);
+        (
+            SteamGuard {
+                shared_secret,
+                generated: false,
+                codes,
+                waiting,
+            },
+            rx,
+        )
+    }
+
+    /// A generated code, unless there is no secret or one was already tried.
+    pub fn generated_code(&mut self) -> Option<String> {
+        if self.generated {
+            return None;
+        }
+        let secret = self.shared_secret.as_ref()?;
+        self.generated = true;
+        let now = chrono::Utc::now().timestamp() as u64;
+        match generate_code(secret, now) {
+            Ok(code) => Some(code),
+            Err(e) => {
+                error!("could not generate a Steam Guard code: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Readies the guard for another steamcmd run of the same job, which logs
+    /// in afresh.
+    pub fn new_attempt(&mut self) {
+        self.generated = false;
+        self.set_waiting(false);
+    }
+
+    pub async fn operator_code(&mut self) -> Option<String> {
+        self.codes.recv().await
+    }
+
+    pub fn set_waiting(&self, waiting: bool) {
+        // Nobody tracking the state is not a reason to stop the install.
+        let _ = self.waiting.send(waiting);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate_code() -> Result<()> {
+        let secret = "cnOgv/KdpLoP6Nbh0GMkXkPXALQ=";
+        assert_eq!("W3J46", generate_code(secret, 0)?);
+        assert_eq!("H6G3P", generate_code(secret, 1_600_000_000)?);
+        // Codes only change every thirty seconds.
+        assert_eq!("H6G3P", generate_code(secret, 1_600_000_009)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_generated_once_per_attempt() {
+        let (_codes, rx) = mpsc::channel(1);
+        let (mut guard, _) = SteamGuard::new(Some("cnOgv/KdpLoP6Nbh0GMkXkPXALQ=".into()), rx);
+        assert!(guard.generated_code().is_some());
+        assert!(guard.generated_code().is_none());
+
+        guard.new_attempt();
+        assert!(guard.generated_code().is_some());
+    }
+
+    #[test]
+    fn test_bad_secret() {
+        assert!(generate_code("not base64!", 0).is_err());
+    }
+
+    #[test]
+    fn test_is_prompt() {
+        assert!(is_prompt("Steam Guard code:"));
+        assert!(is_prompt("Two-factor code: "));
+        assert!(!is_prompt("Logging in user 'operator' to Steam Public..."));
+    }
+}
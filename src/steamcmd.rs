@@ -0,0 +1,196 @@
+use std::path::{Path, PathBuf};
+
+use crate::schema::steamcmd_installs;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::{Insertable, Queryable};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+
+/// A steamcmd that was downloaded and brought up to date by the manager.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Queryable)]
+pub struct SteamCmdInstall {
+    pub id: i32,
+    pub version: Option<String>,
+    pub sha256: String,
+    pub installed_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "steamcmd_installs"]
+pub struct NewSteamCmdInstall {
+    pub version: Option<String>,
+    pub sha256: String,
+    pub installed_at: NaiveDateTime,
+}
+
+/// Downloads the steamcmd tarball and unpacks it where `steamcmd_location`
+/// expects to find it.
+#[derive(Clone)]
+pub struct Bootstrap {
+    location: PathBuf,
+    url: String,
+    sha256: Option<String>,
+}
+
+impl Bootstrap {
+    pub fn new(location: &str, url: &str, sha256: Option<&str>) -> Self {
+        Bootstrap {
+            location: location.into(),
+            url: url.into(),
+            sha256: sha256.map(|s| s.trim().to_lowercase()),
+        }
+    }
+
+    pub fn location(&self) -> &Path {
+        &self.location
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.location.is_file()
+    }
+
+    pub async fn install(&self) -> Result<NewSteamCmdInstall> {
+        debug!("downloading steamcmd from {}", self.url);
+        let tarball = reqwest::get(&self.url)
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let sha256 = format!("{:x}", Sha256::digest(&tarball));
+        match &self.sha256 {
+            Some(expected) if *expected != sha256 => anyhow::bail!(
+                "steamcmd download has checksum {} rather than {}",
+                sha256,
+                expected
+            ),
+            Some(_) => {}
+            None => warn!("no checksum configured for steamcmd, downloaded {}", sha256),
+        }
+
+        let dir = self.dir();
+        tokio::task::spawn_blocking({
+            let dir = dir.clone();
+            move || unpack(&tarball, &dir)
+        })
+        .await??;
+        if !self.is_installed() {
+            anyhow::bail!(
+                "steamcmd download did not contain {}",
+                self.location.display()
+            );
+        }
+
+        let version = self.self_update().await?;
+        Ok(NewSteamCmdInstall {
+            version,
+            sha256,
+            installed_at: chrono::Utc::now().naive_utc(),
+        })
+    }
+
+    fn dir(&self) -> PathBuf {
+        match self.location.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    /// Runs steamcmd once, which has it fetch the rest of itself.
+    async fn self_update(&self) -> Result<Option<String>> {
+        // Relative locations would otherwise be looked up from `dir`.
+        let output = Command::new(std::fs::canonicalize(&self.location)?)
+            .arg("+quit")
+            .current_dir(self.dir())
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let version = stdout.lines().find_map(version);
+        debug!("steamcmd self-update exited with {}", output.status);
+        // steamcmd restarts itself after updating and does not always exit
+        // cleanly when it does, so a reported version is taken as success.
+        if version.is_none() && !output.status.success() {
+            anyhow::bail!("steamcmd self-update exited with {}", output.status);
+        }
+        Ok(version)
+    }
+}
+
+fn unpack(tarball: &[u8], dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball));
+    archive.set_preserve_permissions(true);
+    archive.unpack(dir)?;
+    Ok(())
+}
+
+/// Reads the version from steamcmd's banner, e.g.
+/// `Steam Console Client (c) Valve Corporation - version 1698262904`.
+fn version(line: &str) -> Option<String> {
+    let (_, version) = line.split_once("Steam Console Client")?;
+    let (_, version) = version.rsplit_once("version ")?;
+    Some(version.trim().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use mockito::mock;
+
+    use super::*;
+
+    fn tarball(script: &str) -> anyhow::Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            vec![],
+            flate2::Compression::default(),
+        ));
+        let script = format!("#!/bin/sh\n{}\n", script);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(script.len() as u64);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder.append_data(&mut header, "steamcmd.sh", script.as_bytes())?;
+        Ok(builder.into_inner()?.finish()?)
+    }
+
+    #[test]
+    fn test_version() {
+        assert_eq!(
+            Some(String::from("1698262904")),
+            version("Steam Console Client (c) Valve Corporation - version 1698262904")
+        );
+        assert_eq!(None, version("Loading Steam API...OK"));
+    }
+
+    #[tokio::test]
+    async fn test_install() -> anyhow::Result<()> {
+        let tarball = tarball(
+            "echo 'Steam Console Client (c) Valve Corporation - version 1698262904'\nexit 7",
+        )?;
+        let sha256 = format!("{:x}", Sha256::digest(&tarball));
+        let _m = mock("GET", "/client/installer/steamcmd_linux.tar.gz")
+            .with_status(200)
+            .with_body(&tarball)
+            .create();
+        let url = format!(
+            "{}/client/installer/steamcmd_linux.tar.gz",
+            mockito::server_url()
+        );
+        let dir = tempfile::tempdir()?;
+        let location = dir.path().join("steamcmd").join("steamcmd.sh");
+        let location = location.to_str().unwrap();
+
+        let wrong = Bootstrap::new(location, &url, Some(&"0".repeat(64)));
+        assert!(wrong.install().await.is_err());
+        assert!(!wrong.is_installed());
+
+        let bootstrap = Bootstrap::new(location, &url, Some(&sha256.to_uppercase()));
+        let installed = bootstrap.install().await?;
+        assert!(bootstrap.is_installed());
+        assert_eq!(Some(String::from("1698262904")), installed.version);
+        assert_eq!(sha256, installed.sha256);
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::snapshot::SnapshotMode;
+
 #[derive(Serialize, Deserialize)]
 pub struct ServerConfig {
     pub steamcmd_location: String,
@@ -7,4 +9,38 @@ pub struct ServerConfig {
     pub database_url: String,
     pub base_dir: String,
     pub workers: usize,
+    /// Queue jobs that were running when the manager stopped again on
+    /// startup, rather than only marking them interrupted.
+    pub requeue_interrupted: bool,
+    /// Space installs must leave free on the disk holding `base_dir`.
+    pub min_free_space_mb: u64,
+    /// Copy each server's install directory before updating it, as a plain
+    /// copy that is cloned copy-on-write where the filesystem can, or as a
+    /// tar archive.
+    pub snapshot_mode: SnapshotMode,
+    /// Where snapshots are kept, by default `.snapshots` in `base_dir` so
+    /// that clones can share the installs' blocks.
+    #[serde(default)]
+    pub snapshot_dir: Option<String>,
+    /// Snapshots kept for each server.
+    pub snapshot_retention: usize,
+    /// Restore the snapshot when the update it was taken for fails.
+    pub rollback_failed_updates: bool,
+    /// How many times a transient steamcmd failure is retried.
+    pub install_retries: u32,
+    /// Wait before the first retry, doubling for each one after.
+    pub retry_backoff_seconds: u64,
+    /// Longest a single steamcmd run may take, or `0` for no limit.
+    pub install_timeout_seconds: u64,
+    /// Longest steamcmd may go without printing anything, or `0` for no limit.
+    pub output_timeout_seconds: u64,
+    pub steamcmd_url: String,
+    /// SHA-256 of the steamcmd tarball, checked when bootstrapping.
+    #[serde(default)]
+    pub steamcmd_sha256: Option<String>,
+    /// Download steamcmd on startup when it is not at `steamcmd_location`.
+    pub steamcmd_bootstrap: bool,
+    /// How often installed servers are checked for a newer build, or `0` to
+    /// never check.
+    pub update_check_minutes: u64,
 }
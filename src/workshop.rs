@@ -0,0 +1,463 @@
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::install::Server;
+use crate::schema::workshop_items;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::{sql_types::Text, Insertable, Queryable};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum ItemStatus {
+    /// Added to the server but not downloaded yet.
+    Pending,
+    Downloaded,
+    Failed,
+}
+
+impl ItemStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ItemStatus::Pending => "pending",
+            ItemStatus::Downloaded => "downloaded",
+            ItemStatus::Failed => "failed",
+        }
+    }
+}
+
+impl fmt::Display for ItemStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ItemStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(ItemStatus::Pending),
+            "downloaded" => Ok(ItemStatus::Downloaded),
+            "failed" => Ok(ItemStatus::Failed),
+            other => Err(anyhow::anyhow!("unknown workshop item status: {}", other)),
+        }
+    }
+}
+
+text_column!(ItemStatus);
+
+/// How downloaded Workshop content is put into a server's mods directory.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, AsExpression, FromSqlRow,
+)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum ModLink {
+    #[default]
+    Symlink,
+    Copy,
+}
+
+impl ModLink {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModLink::Symlink => "symlink",
+            ModLink::Copy => "copy",
+        }
+    }
+}
+
+impl fmt::Display for ModLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ModLink {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "symlink" => Ok(ModLink::Symlink),
+            "copy" => Ok(ModLink::Copy),
+            other => Err(anyhow::anyhow!("unknown mod link: {}", other)),
+        }
+    }
+}
+
+text_column!(ModLink);
+
+/// A Workshop item a server downloads alongside its app.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Queryable, Insertable)]
+#[table_name = "workshop_items"]
+pub struct WorkshopItem {
+    pub server_id: i32,
+    pub item_id: i64,
+    pub status: ItemStatus,
+    pub error_message: Option<String>,
+    pub updated_at: Option<NaiveDateTime>,
+    /// Where the item comes in the server's mod load order.
+    pub position: i32,
+}
+
+impl WorkshopItem {
+    pub fn pending(server_id: i32, item_id: i64, position: i32) -> Self {
+        WorkshopItem {
+            server_id,
+            item_id,
+            status: ItemStatus::Pending,
+            error_message: None,
+            updated_at: None,
+            position,
+        }
+    }
+}
+
+/// A `GetCollectionDetails` response from the Steam Web API, which is also
+/// the format accepted for collections supplied by hand.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CollectionResponse {
+    pub response: CollectionDetails,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CollectionDetails {
+    #[serde(default)]
+    pub collectiondetails: Vec<Collection>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Collection {
+    pub publishedfileid: String,
+    pub result: i32,
+    #[serde(default)]
+    pub children: Vec<CollectionChild>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CollectionChild {
+    pub publishedfileid: String,
+    pub sortorder: i32,
+    pub filetype: i32,
+}
+
+/// `filetype` of a collection child that is an item rather than another
+/// collection.
+const ITEM_FILETYPE: i32 = 0;
+
+impl CollectionResponse {
+    /// The collection's items in the order they appear in it. Collections
+    /// linked from this one are not followed.
+    pub fn items(&self, collection_id: i64) -> Result<Vec<i64>> {
+        let collection = self
+            .response
+            .collectiondetails
+            .iter()
+            .find(|c| c.publishedfileid == collection_id.to_string())
+            .ok_or_else(|| anyhow::anyhow!("no details for collection {}", collection_id))?;
+        if collection.result != 1 {
+            anyhow::bail!(
+                "Steam could not find collection {} (result {})",
+                collection_id,
+                collection.result
+            );
+        }
+        let mut children: Vec<&CollectionChild> = collection
+            .children
+            .iter()
+            .filter(|c| c.filetype == ITEM_FILETYPE)
+            .collect();
+        children.sort_by_key(|c| c.sortorder);
+        children
+            .iter()
+            .map(|c| Ok(c.publishedfileid.parse()?))
+            .collect()
+    }
+}
+
+/// Looks collections up through the Steam Web API.
+#[derive(Clone, Default)]
+pub struct Client {
+    url: String,
+}
+
+impl Client {
+    pub fn new(url: &str) -> Self {
+        Client { url: url.into() }
+    }
+
+    pub async fn collection(&self, collection_id: i64) -> Result<CollectionResponse> {
+        let path = "/ISteamRemoteStorage/GetCollectionDetails/v1/";
+        let id = collection_id.to_string();
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", &self.url, path))
+            .form(&[
+                ("collectioncount", "1"),
+                ("publishedfileids[0]", id.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(serde_json::from_str(&response.text().await?)?)
+    }
+}
+
+/// The arguments that load a server's mods, in order, for the games whose
+/// format is known.
+pub fn launch_args(server: &Server, items: &[WorkshopItem]) -> Result<Vec<String>> {
+    let paths: Vec<String> = items
+        .iter()
+        .map(|item| match &server.mods_dir {
+            Some(mods_dir) => format!("{}/{}", mods_dir.trim_end_matches('/'), item.item_id),
+            None => format!(
+                "steamapps/workshop/content/{}/{}",
+                server.workshop_app(),
+                item.item_id
+            ),
+        })
+        .collect();
+    if paths.is_empty() {
+        return Ok(vec![]);
+    }
+    match server.id {
+        // Arma 3 and DayZ dedicated servers.
+        233780 | 223350 => Ok(vec![format!("-mod={}", paths.join(";"))]),
+        other => anyhow::bail!("launch arguments for mods are not known for app {}", other),
+    }
+}
+
+/// Checks that a new load order lists only the server's own items, each of
+/// them at most once.
+pub fn check_order(items: &[WorkshopItem], order: &[i64]) -> Result<()> {
+    let mut listed = HashSet::new();
+    for item_id in order {
+        if !listed.insert(item_id) {
+            anyhow::bail!("workshop item {} is listed more than once", item_id);
+        }
+        if !items.iter().any(|item| item.item_id == *item_id) {
+            anyhow::bail!("workshop item {} is not one of the server's mods", item_id);
+        }
+    }
+    Ok(())
+}
+
+/// What steamcmd reported for one `workshop_download_item`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItemDownload {
+    Downloaded { item_id: i64, path: PathBuf },
+    Failed { item_id: i64, message: String },
+}
+
+impl ItemDownload {
+    /// Recognises steamcmd's result lines, e.g.
+    /// `Success. Downloaded item 450814997 to "/srv/arma3/steamapps/workshop/content/107410/450814997" (73014 bytes)`
+    /// or `ERROR! Download item 450814997 failed (Failure).`
+    pub fn from_line(line: &str) -> Option<Self> {
+        let item_after = |marker: &str| -> Option<i64> {
+            let (_, rest) = line.split_once(marker)?;
+            let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().ok()
+        };
+        if let Some(item_id) = item_after("Downloaded item ") {
+            let path = line.split('"').nth(1)?;
+            return Some(ItemDownload::Downloaded {
+                item_id,
+                path: path.into(),
+            });
+        }
+        let failed = match item_after("Download item ") {
+            Some(item_id) if line.contains("failed") => Some(item_id),
+            _ => item_after("Timeout downloading item "),
+        };
+        failed.map(|item_id| ItemDownload::Failed {
+            item_id,
+            message: line.trim().to_string(),
+        })
+    }
+
+    pub fn item_id(&self) -> i64 {
+        match self {
+            ItemDownload::Downloaded { item_id, .. } | ItemDownload::Failed { item_id, .. } => {
+                *item_id
+            }
+        }
+    }
+}
+
+/// Puts a downloaded item into `mods_dir` under `install_dir`, named after
+/// its ID and replacing whatever was there before.
+pub fn link_item(
+    source: &Path,
+    install_dir: &Path,
+    mods_dir: &str,
+    item_id: i64,
+    link: ModLink,
+) -> Result<PathBuf> {
+    let inside = Path::new(mods_dir)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        anyhow::bail!(
+            "mods directory {} is not inside the install directory",
+            mods_dir
+        );
+    }
+    let mods_dir = install_dir.join(mods_dir);
+    fs::create_dir_all(&mods_dir)?;
+    let target = mods_dir.join(item_id.to_string());
+    if let Ok(existing) = fs::symlink_metadata(&target) {
+        if existing.is_dir() {
+            fs::remove_dir_all(&target)?;
+        } else {
+            fs::remove_file(&target)?;
+        }
+    }
+    match link {
+        ModLink::Symlink => std::os::unix::fs::symlink(source, &target)?,
+        ModLink::Copy => copy_dir(source, &target)?,
+    }
+    Ok(target)
+}
+
+fn copy_dir(source: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let to = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to)?;
+        } else {
+            fs::copy(entry.path(), to)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use mockito::mock;
+
+    use super::*;
+
+    fn collection() -> anyhow::Result<CollectionResponse> {
+        Ok(serde_json::from_str(&fs::read_to_string(
+            "./test_data/workshop_collection.json",
+        )?)?)
+    }
+
+    #[test]
+    fn test_collection_items() -> anyhow::Result<()> {
+        assert_eq!(
+            vec![450814997, 463939057, 843577117],
+            collection()?.items(1751569185)?
+        );
+        assert!(collection()?.items(42).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_collection() -> anyhow::Result<()> {
+        let _m = mock("POST", "/ISteamRemoteStorage/GetCollectionDetails/v1/")
+            .match_body("collectioncount=1&publishedfileids%5B0%5D=1751569185")
+            .with_status(200)
+            .with_body_from_file("./test_data/workshop_collection.json")
+            .create();
+
+        let client = Client::new(&mockito::server_url());
+        assert_eq!(collection()?, client.collection(1751569185).await?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_launch_args() -> anyhow::Result<()> {
+        let mut server = Server::new(233780, "arma3", "anonymous", "arma3");
+        server.workshop_app_id = Some(107410);
+        let items = vec![
+            WorkshopItem::pending(233780, 450814997, 0),
+            WorkshopItem::pending(233780, 463939057, 1),
+        ];
+        assert_eq!(
+            vec!["-mod=steamapps/workshop/content/107410/450814997;steamapps/workshop/content/107410/463939057"],
+            launch_args(&server, &items)?
+        );
+
+        server.mods_dir = Some("mods/".into());
+        assert_eq!(
+            vec!["-mod=mods/450814997;mods/463939057"],
+            launch_args(&server, &items)?
+        );
+
+        let server = Server::new(740, "csgo", "anonymous", "csgo");
+        assert!(launch_args(&server, &items).is_err());
+        assert!(launch_args(&server, &[])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_order() {
+        let items = vec![
+            WorkshopItem::pending(233780, 450814997, 0),
+            WorkshopItem::pending(233780, 463939057, 1),
+        ];
+        assert!(check_order(&items, &[463939057, 450814997]).is_ok());
+        assert!(check_order(&items, &[463939057]).is_ok());
+        assert!(check_order(&items, &[463939057, 463939057]).is_err());
+        assert!(check_order(&items, &[843577117]).is_err());
+    }
+
+    #[test]
+    fn test_download_from_line() {
+        assert_eq!(
+            Some(ItemDownload::Downloaded {
+                item_id: 450814997,
+                path: "/srv/arma3/steamapps/workshop/content/107410/450814997".into(),
+            }),
+            ItemDownload::from_line(
+                "Success. Downloaded item 450814997 to \"/srv/arma3/steamapps/workshop/content/107410/450814997\" (73014 bytes) "
+            )
+        );
+        assert_eq!(
+            Some(450814997),
+            ItemDownload::from_line("ERROR! Download item 450814997 failed (Failure).")
+                .map(|d| d.item_id())
+        );
+        assert_eq!(
+            Some(2867537125),
+            ItemDownload::from_line("ERROR! Timeout downloading item 2867537125")
+                .map(|d| d.item_id())
+        );
+        assert_eq!(
+            None,
+            ItemDownload::from_line("Downloading item 450814997 ...")
+        );
+    }
+
+    #[test]
+    fn test_link_item() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir
+            .path()
+            .join("steamapps/workshop/content/107410/450814997");
+        fs::create_dir_all(source.join("addons"))?;
+        fs::write(source.join("addons/cba.pbo"), "pbo")?;
+
+        let linked = link_item(&source, dir.path(), "mods", 450814997, ModLink::Symlink)?;
+        assert_eq!(source, fs::read_link(&linked)?);
+
+        // Switching to a copy replaces the link.
+        let copied = link_item(&source, dir.path(), "mods", 450814997, ModLink::Copy)?;
+        assert!(!fs::symlink_metadata(&copied)?.file_type().is_symlink());
+        assert_eq!("pbo", fs::read_to_string(copied.join("addons/cba.pbo"))?);
+
+        assert!(link_item(&source, dir.path(), "../mods", 450814997, ModLink::Copy).is_err());
+        assert!(link_item(&source, dir.path(), "/tmp/mods", 450814997, ModLink::Copy).is_err());
+        Ok(())
+    }
+}